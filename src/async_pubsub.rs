@@ -0,0 +1,105 @@
+use std::collections::hash_map::HashMap;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+
+use super::Event;
+
+pub type ListenerFuture<'c, Channel, Payload> = Pin<Box<dyn Future<Output = Vec<Event<Channel, Payload>>> + 'c>>;
+pub type AsyncListener<Context, Channel, Payload> = for<'c> fn(&'c mut Context, Payload) -> ListenerFuture<'c, Channel, Payload>;
+
+pub struct AsyncPubsub<'a, Context: 'a, Channel: Hash + Eq + Clone, Payload: Clone> {
+  pub context: &'a mut Context,
+  listeners: HashMap<Channel, Vec<AsyncListener<Context, Channel, Payload>>>,
+  event_queue: VecDeque<Event<Channel, Payload>>
+}
+
+impl<'a, Context, Channel: Hash + Eq + Clone, Payload: Clone> AsyncPubsub<'a, Context, Channel, Payload> {
+  pub fn new(context: &'a mut Context) -> AsyncPubsub<'a, Context, Channel, Payload> {
+    AsyncPubsub {
+      context,
+      listeners: HashMap::new(),
+      event_queue: VecDeque::new()
+    }
+  }
+
+  pub fn subscribe(&mut self, channel: Channel, listener: AsyncListener<Context, Channel, Payload>) {
+    self.listeners.entry(channel).or_default().push(listener);
+  }
+
+  pub async fn publish(&mut self, event: Event<Channel, Payload>) -> usize {
+    let mut invoked = 0;
+    self.event_queue.push_back(event);
+    while let Some(Event { channel, payload }) = self.event_queue.pop_front() {
+      let listeners = match self.listeners.get(&channel) {
+        Some(listeners) => listeners.clone(),
+        None => continue
+      };
+      for listener in listeners {
+        invoked += 1;
+        let tail = listener(&mut *self.context, payload.clone()).await;
+        self.event_queue.extend(tail);
+      }
+    }
+    invoked
+  }
+}
+
+#[cfg(test)]
+fn block_on<F: Future>(future: F) -> F::Output {
+  use std::task::{Context, Poll, Waker};
+
+  let mut future = Box::pin(future);
+  let mut cx = Context::from_waker(Waker::noop());
+  loop {
+    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+      return output;
+    }
+  }
+}
+
+#[test]
+fn async_listener_should_await_before_updating_context() {
+  use std::task::{Context, Poll};
+
+  struct YieldNow(bool);
+
+  impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+      if self.0 {
+        return Poll::Ready(());
+      }
+      self.0 = true;
+      cx.waker().wake_by_ref();
+      Poll::Pending
+    }
+  }
+
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: AsyncPubsub<TestContext, String, String> = AsyncPubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, _msg: String) -> ListenerFuture<'_, String, String> {
+    Box::pin(async move {
+      YieldNow(false).await;
+      context.data += 1;
+      Vec::new()
+    })
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  let invoked = block_on(pubsub.publish(event));
+
+  assert!(invoked == 1);
+  assert!(pubsub.context.data == 1)
+}
@@ -0,0 +1,171 @@
+use std::hash::Hash;
+
+use super::{Event, Pubsub};
+
+pub trait Routable<Channel> {
+  fn channel(&self) -> Channel;
+}
+
+pub struct EventBuilder<Channel> {
+  channel: Channel
+}
+
+impl<Channel> EventBuilder<Channel> {
+  pub fn new(channel: Channel) -> EventBuilder<Channel> {
+    EventBuilder { channel }
+  }
+
+  pub fn payload<Payload>(self, payload: Payload) -> Event<Channel, Payload> {
+    Event {
+      channel: self.channel,
+      payload
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct PubsubBuilder {
+  max_depth: usize,
+  history: usize,
+  metrics: bool,
+  timing: bool,
+  max_queue: usize,
+  strict: bool
+}
+
+impl Default for PubsubBuilder {
+  fn default() -> PubsubBuilder {
+    PubsubBuilder::new()
+  }
+}
+
+impl PubsubBuilder {
+  pub fn new() -> PubsubBuilder {
+    PubsubBuilder {
+      max_depth: usize::MAX,
+      history: 0,
+      metrics: false,
+      timing: false,
+      max_queue: usize::MAX,
+      strict: false
+    }
+  }
+
+  pub fn max_depth(mut self, max_depth: usize) -> PubsubBuilder {
+    self.max_depth = max_depth;
+    self
+  }
+
+  pub fn history(mut self, capacity: usize) -> PubsubBuilder {
+    self.history = capacity;
+    self
+  }
+
+  pub fn metrics(mut self, enabled: bool) -> PubsubBuilder {
+    self.metrics = enabled;
+    self
+  }
+
+  pub fn timing(mut self, enabled: bool) -> PubsubBuilder {
+    self.timing = enabled;
+    self
+  }
+
+  pub fn max_queue(mut self, cap: usize) -> PubsubBuilder {
+    self.max_queue = cap;
+    self
+  }
+
+  pub fn strict(mut self, enabled: bool) -> PubsubBuilder {
+    self.strict = enabled;
+    self
+  }
+
+  pub fn build<'a, Context, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a>(self, context: &'a mut Context) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.max_depth = self.max_depth;
+    pubsub.history_capacity = self.history;
+    pubsub.metrics_enabled = self.metrics;
+    pubsub.timing_enabled = self.timing;
+    pubsub.max_queue = self.max_queue;
+    pubsub.strict = self.strict;
+    pubsub
+  }
+}
+
+pub fn route<Channel, Payload: Routable<Channel>>(payload: Payload) -> Event<Channel, Payload> {
+  EventBuilder::new(payload.channel()).payload(payload)
+}
+
+#[test]
+fn builder_should_construct_event() {
+  let event = EventBuilder::new("test channel".to_string()).payload(1);
+
+  assert_eq!(event, Event { channel: "test channel".to_string(), payload: 1 });
+}
+
+#[test]
+fn route_should_derive_channel_from_payload() {
+  struct Ping;
+
+  impl Routable<String> for Ping {
+    fn channel(&self) -> String {
+      "ping".to_string()
+    }
+  }
+
+  let event = route(Ping);
+
+  assert!(event.channel == "ping")
+}
+
+#[test]
+fn pubsub_builder_should_apply_history_and_max_depth() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = PubsubBuilder::new().history(2).max_depth(3).build(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "test channel".to_string(), payload: msg }]
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  assert!(pubsub.publish(event) == 3);
+  assert!(pubsub.history().len() == 2);
+  assert!(pubsub.context.data == 3)
+}
+
+#[test]
+fn pubsub_builder_should_apply_timing_and_max_queue() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = PubsubBuilder::new().timing(true).max_queue(1).build(&mut test_context);
+
+  fn listener(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "leaf".to_string(), payload: msg.clone() },
+      Event { channel: "leaf".to_string(), payload: msg }
+    ]
+  }
+
+  fn leaf_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  pubsub.subscribe("root".to_string(), listener);
+  pubsub.subscribe("leaf".to_string(), leaf_listener);
+
+  assert!(pubsub.publish_bounded(Event { channel: "root".to_string(), payload: "go".to_string() }).is_err());
+  assert!(pubsub.timing().total.contains_key("root"));
+  assert!(pubsub.timing().total.contains_key("leaf"))
+}
@@ -0,0 +1,453 @@
+use std::collections::vec_deque::{self, VecDeque};
+use std::hash::Hash;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::{Ancestry, BusError, CycleError, Event, Hook, Invocation, PanicHandler, ProcessedEvent, Pubsub, Subscription, Trace, UnroutedEvent};
+
+// The bus's pending events. Each one carries its own trace, so removing or
+// reordering events can't hand one event's request or ancestry to another.
+// Events a queue-aware listener pushes join the queue once it returns, through
+// the same max_queue and coalescing checks as any other.
+pub struct EventQueue<Channel, Payload> {
+  entries: VecDeque<(Event<Channel, Payload>, Trace<Channel, Payload>)>,
+  pushed: Vec<Event<Channel, Payload>>
+}
+
+impl<Channel, Payload> EventQueue<Channel, Payload> {
+  pub(super) fn new() -> EventQueue<Channel, Payload> {
+    EventQueue { entries: VecDeque::new(), pushed: Vec::new() }
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn get(&self, index: usize) -> Option<&Event<Channel, Payload>> {
+    self.entries.get(index).map(|(event, _)| event)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Event<Channel, Payload>> {
+    self.entries.iter().map(|(event, _)| event)
+  }
+
+  pub fn retain<F: FnMut(&Event<Channel, Payload>) -> bool>(&mut self, mut keep: F) {
+    self.entries.retain(|(event, _)| keep(event));
+  }
+
+  pub fn remove(&mut self, index: usize) -> Option<Event<Channel, Payload>> {
+    self.entries.remove(index).map(|(event, _)| event)
+  }
+
+  pub fn push_back(&mut self, event: Event<Channel, Payload>) {
+    self.pushed.push(event);
+  }
+
+  pub(super) fn push(&mut self, event: Event<Channel, Payload>, trace: Trace<Channel, Payload>) {
+    self.entries.push_back((event, trace));
+  }
+
+  pub(super) fn take(&mut self, index: usize) -> Option<(Event<Channel, Payload>, Trace<Channel, Payload>)> {
+    self.entries.remove(index)
+  }
+
+  pub(super) fn get_mut(&mut self, index: usize) -> Option<&mut Event<Channel, Payload>> {
+    self.entries.get_mut(index).map(|(event, _)| event)
+  }
+
+  pub(super) fn drain(&mut self) -> vec_deque::Drain<'_, (Event<Channel, Payload>, Trace<Channel, Payload>)> {
+    self.entries.drain(..)
+  }
+
+  pub(super) fn take_pushed(&mut self) -> Vec<Event<Channel, Payload>> {
+    std::mem::take(&mut self.pushed)
+  }
+
+  pub(super) fn clear(&mut self) {
+    self.entries.clear();
+    self.pushed.clear();
+  }
+}
+
+// next returns the position of the queued event to dispatch.
+pub trait Dispatcher<Channel, Payload> {
+  fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize>;
+}
+
+// The dispatcher a bus holds. fork copies it in its current state, so a forked
+// bus schedules on its own instance and never advances this one.
+pub(super) trait Installed<'a, Channel, Payload> {
+  fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize>;
+  fn fork(&self) -> Box<dyn Installed<'a, Channel, Payload> + 'a>;
+}
+
+impl<'a, Channel, Payload, D: Dispatcher<Channel, Payload> + Clone + 'a> Installed<'a, Channel, Payload> for D {
+  fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize> {
+    Dispatcher::next(self, queue)
+  }
+
+  fn fork(&self) -> Box<dyn Installed<'a, Channel, Payload> + 'a> {
+    Box::new(self.clone())
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Fifo;
+
+impl<Channel, Payload> Dispatcher<Channel, Payload> for Fifo {
+  fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize> {
+    if queue.is_empty() {
+      None
+    } else {
+      Some(0)
+    }
+  }
+}
+
+// Clears the bus's processing flag when dropped, so a listener that panics
+// without a handler doesn't leave the bus busy for good.
+struct Processing(Rc<Cell<bool>>);
+
+impl Processing {
+  fn start(flag: &Rc<Cell<bool>>) -> Processing {
+    flag.set(true);
+    Processing(flag.clone())
+  }
+}
+
+impl Drop for Processing {
+  fn drop(&mut self) {
+    self.0.set(false);
+  }
+}
+
+fn chain<Channel: Clone>(mut ancestry: Option<&Rc<Ancestry<Channel>>>) -> Vec<Channel> {
+  let mut path = Vec::new();
+  while let Some(node) = ancestry {
+    path.push(node.channel.clone());
+    ancestry = node.parent.as_ref();
+  }
+  path.reverse();
+  path
+}
+
+fn first_cycle<Channel: PartialEq + Clone>(path: &[Channel]) -> Option<Vec<Channel>> {
+  let (start, end) = (1..path.len()).find_map(|end| path[..end].iter().position(|channel| *channel == path[end]).map(|start| (start, end)))?;
+  Some(path[start..=end].to_vec())
+}
+
+fn invoke<'a, Context, Channel, Payload, E>(
+  subscription: &Subscription<'a, Context, Channel, Payload, E>,
+  context: &mut Context,
+  call: &mut Invocation<Channel, Payload>,
+  payload: &Rc<Payload>,
+  listener_hook: Option<(Hook<Channel, Payload>, Hook<Channel, Payload>)>,
+  panic_handler: Option<PanicHandler<Channel>>
+) -> Result<Vec<Event<Channel, Payload>>, E> {
+  if let Some((before, _)) = listener_hook {
+    before(call.channel, payload);
+  }
+  let outcome = match panic_handler {
+    Some(handler) => panic::catch_unwind(AssertUnwindSafe(|| (subscription.callback.borrow_mut())(&mut *context, &mut *call, payload.clone()))).unwrap_or_else(|_| {
+      handler(call.channel);
+      Ok(Vec::new())
+    }),
+    None => (subscription.callback.borrow_mut())(context, call, payload.clone())
+  };
+  if let Some((_, after)) = listener_hook {
+    after(call.channel, payload);
+  }
+  outcome
+}
+
+impl<'a, Context, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a> Pubsub<'a, Context, Channel, Payload, E> {
+  pub(super) fn process_event(&mut self, event: Event<Channel, Payload>, trace: Trace<Channel, Payload>, invoked: &mut usize) -> Result<(), E> {
+    let event = Event { channel: self.normalize(event.channel), payload: event.payload };
+    let seq = self.seq;
+    self.seq += 1;
+    if self.history_capacity > 0 {
+      if self.history.len() == self.history_capacity {
+        self.history.pop_front();
+      }
+      self.history.push_back(ProcessedEvent { seq, event: event.clone() });
+    }
+    if let Some(ref mut collected) = self.collected {
+      collected.push(ProcessedEvent { seq, event: event.clone() });
+    }
+    if self.metrics_enabled {
+      self.metrics.total_events_processed += 1;
+      *self.metrics.channel_dispatches.entry(event.channel.clone()).or_insert(0) += 1;
+    }
+
+    let context = &mut *self.context;
+    let Event { channel, payload } = event;
+    let payload = self.middleware.iter().fold(payload, |payload, middleware| middleware(&channel, payload));
+    let payload = Rc::new(payload);
+    let mut emitted = Vec::new();
+    let mut routed = false;
+    let depth = trace.depth;
+
+    if let Some(listeners) = self.listeners.get_mut(&channel) {
+      listeners.retain(|s| s.is_alive());
+      routed = !listeners.is_empty();
+      let disabled_groups = &self.disabled_groups;
+      let mut spent = Vec::new();
+      let mut result = Ok(());
+      for (index, subscription) in listeners.iter_mut().enumerate() {
+        if subscription.group.is_some_and(|group| disabled_groups.contains(&group)) || !subscription.accepts(&payload) {
+          continue;
+        }
+        *invoked += 1;
+        if subscription.once {
+          spent.push(subscription.id);
+        }
+        let start = self.timing_enabled.then(Instant::now);
+        if let Some(listener) = subscription.queue_listener {
+          if let Some((before, _)) = self.listener_hook {
+            before(&channel, &payload);
+          }
+          let queue = &mut self.event_queue;
+          match self.panic_handler {
+            Some(handler) => {
+              if panic::catch_unwind(AssertUnwindSafe(|| listener(&mut *context, (*payload).clone(), &mut *queue))).is_err() {
+                handler(&channel);
+              }
+            },
+            None => listener(context, (*payload).clone(), queue)
+          }
+          if let Some((_, after)) = self.listener_hook {
+            after(&channel, &payload);
+          }
+          if let Some(start) = start {
+            self.timing.record(&channel, start.elapsed());
+          }
+          continue;
+        }
+        let forward = self.forwarders.get(&subscription.id).map(|sink| &**sink);
+        let mut call = Invocation { channel: &channel, depth, index, deferred: &mut self.deferred, forward };
+        let outcome = invoke(subscription, &mut *context, &mut call, &payload, self.listener_hook, self.panic_handler);
+        if let Some(start) = start {
+          self.timing.record(&channel, start.elapsed());
+        }
+        match outcome {
+          Ok(tail) => emitted.extend(tail),
+          Err(error) => {
+            result = Err(error);
+            break;
+          }
+        }
+        if subscription.stop.as_ref().is_some_and(|stop| stop.replace(false)) {
+          break;
+        }
+      }
+
+      listeners.retain(|s| !spent.contains(&s.id));
+      if listeners.is_empty() {
+        self.listeners.remove(&channel);
+      }
+      result?;
+    }
+
+    for (index, pattern) in self.pattern_listeners.iter().enumerate() {
+      if !(pattern.matches)(&channel, self.normalizer) {
+        continue;
+      }
+      routed = true;
+      if !pattern.subscription.accepts(&payload) {
+        continue;
+      }
+      *invoked += 1;
+      let start = self.timing_enabled.then(Instant::now);
+      let mut call = Invocation { channel: &channel, depth, index, deferred: &mut self.deferred, forward: None };
+      let outcome = invoke(&pattern.subscription, &mut *context, &mut call, &payload, self.listener_hook, self.panic_handler);
+      if let Some(start) = start {
+        self.timing.record(&channel, start.elapsed());
+      }
+      emitted.extend(outcome?);
+    }
+
+    if !routed {
+      if self.strict {
+        self.last_error = Some(BusError::NoListeners(channel.clone()));
+        self.unrouted = Some(UnroutedEvent(Event { channel: channel.clone(), payload: (*payload).clone() }));
+      }
+      if let Some(handler) = self.dead_letter {
+        handler(context, &channel, &payload);
+      }
+    }
+
+    for (_, listener) in self.global_listeners.iter() {
+      *invoked += 1;
+      let start = self.timing_enabled.then(Instant::now);
+      if let Some((before, _)) = self.listener_hook {
+        before(&channel, &payload);
+      }
+      let tail = match self.panic_handler {
+        Some(handler) => panic::catch_unwind(AssertUnwindSafe(|| (*listener)(&mut *context, channel.clone(), (*payload).clone()))).unwrap_or_else(|_| {
+          handler(&channel);
+          Vec::new()
+        }),
+        None => (*listener)(context, channel.clone(), (*payload).clone())
+      };
+      if let Some((_, after)) = self.listener_hook {
+        after(&channel, &payload);
+      }
+      if let Some(start) = start {
+        self.timing.record(&channel, start.elapsed());
+      }
+      emitted.extend(tail);
+    }
+    let trace = Trace { depth: depth + 1, ..trace };
+    for event in self.event_queue.take_pushed() {
+      self.emit(event, trace.clone());
+    }
+    for event in emitted {
+      if self.strict && !self.is_routed(&event.channel) {
+        self.last_error = Some(BusError::NoListeners(event.channel.clone()));
+        self.unrouted = Some(UnroutedEvent(event));
+        break;
+      }
+      self.emit(event, trace.clone());
+    }
+    if let Some((high, on_high)) = self.watermark {
+      let len = self.event_queue.len();
+      if len >= high && !self.above_watermark {
+        on_high(len);
+      }
+      self.above_watermark = len >= high;
+    }
+    Ok(())
+  }
+
+  // With the default Fifo dispatcher, dispatch is breadth-first: every listener
+  // on an event runs before any of the events they emitted, and emitted events
+  // join the back of the queue.
+  pub(super) fn process_queue(&mut self, invoked: &mut usize, mut budget: usize, observer: &mut dyn FnMut(&Event<Channel, Payload>)) -> Result<(), E> {
+    if self.is_processing() || self.paused {
+      return Ok(());
+    }
+    let processing = Processing::start(&self.handle.processing);
+    self.unrouted = None;
+    self.cycle = None;
+    let mut result = Ok(());
+    let mut dispatched = false;
+    while budget > 0 {
+      let (event, trace) = match self.next_event() {
+        Some(next) => next,
+        None => break
+      };
+      budget -= 1;
+      dispatched = true;
+      if trace.depth >= self.max_depth {
+        let mut path = chain(trace.ancestry.as_ref());
+        path.push(event.channel);
+        self.cycle = Some(match first_cycle(&path) {
+          Some(cycle) => CycleError { path: cycle, repeats: true },
+          None => CycleError { path, repeats: false }
+        });
+        self.clear_queue();
+        break;
+      }
+      let ancestry = match self.max_depth {
+        usize::MAX => None,
+        _ => Some(Rc::new(Ancestry { channel: event.channel.clone(), parent: trace.ancestry }))
+      };
+      let trace = Trace { ancestry, ..trace };
+      observer(&event);
+      if let Err(error) = self.process_event(event, trace, invoked) {
+        self.clear_queue();
+        result = Err(error);
+        break;
+      }
+      if self.unrouted.is_some() {
+        self.clear_queue();
+        break;
+      }
+    }
+    drop(processing);
+    self.apply_pending();
+    if let Some(on_idle) = self.on_idle {
+      if dispatched && self.event_queue.is_empty() {
+        on_idle(&mut self.context);
+      }
+    }
+    result
+  }
+
+  fn emit(&mut self, event: Event<Channel, Payload>, trace: Trace<Channel, Payload>) {
+    if let Some(ref reply) = trace.reply {
+      reply.offer(&self.normalize(event.channel.clone()), &event.payload);
+    }
+    self.push_event(event, trace);
+  }
+
+  pub(super) fn apply_pending(&mut self) {
+    let pending: Vec<Channel> = self.handle.pending_unsubscribes.borrow_mut().drain(..).collect();
+    for channel in pending {
+      self.unsubscribe_all(&channel);
+    }
+  }
+
+  fn is_routed(&self, channel: &Channel) -> bool {
+    let channel = &self.normalize(channel.clone());
+    self.listeners.get(channel).is_some_and(|existing_vec| existing_vec.iter().any(|s| s.is_alive())) || self.pattern_listeners.iter().any(|pattern| (pattern.matches)(channel, self.normalizer))
+  }
+
+  pub(super) fn next_index(&self) -> Option<usize> {
+    self.dispatcher.fork().next(&self.event_queue)
+  }
+
+  pub(super) fn next_event(&mut self) -> Option<(Event<Channel, Payload>, Trace<Channel, Payload>)> {
+    let index = self.dispatcher.next(&self.event_queue)?;
+    self.event_queue.take(index)
+  }
+}
+
+#[test]
+fn lifo_dispatcher_should_reverse_sibling_order() {
+  #[derive(Clone)]
+  struct Lifo;
+
+  impl<Channel, Payload> Dispatcher<Channel, Payload> for Lifo {
+    fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize> {
+      queue.len().checked_sub(1)
+    }
+  }
+
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  fn root_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "leaf".to_string(), payload: "a".to_string() },
+      Event { channel: "leaf".to_string(), payload: "b".to_string() }
+    ]
+  }
+
+  fn leaf_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  let mut fifo_context = TestContext { seen: Vec::new() };
+  let mut fifo: Pubsub<TestContext, String, String> = Pubsub::new(&mut fifo_context);
+  fifo.subscribe("root".to_string(), root_listener);
+  fifo.subscribe("leaf".to_string(), leaf_listener);
+  fifo.publish(Event { channel: "root".to_string(), payload: "go".to_string() });
+
+  let mut lifo_context = TestContext { seen: Vec::new() };
+  let mut lifo: Pubsub<TestContext, String, String> = Pubsub::with_dispatcher(&mut lifo_context, Lifo);
+  lifo.subscribe("root".to_string(), root_listener);
+  lifo.subscribe("leaf".to_string(), leaf_listener);
+  lifo.publish(Event { channel: "root".to_string(), payload: "go".to_string() });
+
+  assert!(fifo.context.seen == vec!["a".to_string(), "b".to_string()]);
+  assert!(lifo.context.seen == vec!["b".to_string(), "a".to_string()])
+}
@@ -1,193 +1,5524 @@
-#![feature(std_misc)]
+use std::any::Any;
+use std::fmt;
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut, Range};
+use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::rc::{Rc, Weak};
+use std::sync::mpsc::Receiver;
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+use builder::Routable;
+use dispatch::{Dispatcher, EventQueue, Fifo, Installed};
+
+pub mod builder;
+pub mod dispatch;
+#[cfg(feature = "async")]
+pub mod async_pubsub;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event<Channel, Payload> {
+  pub channel: Channel,
+  pub payload: Payload
+}
+
+pub enum MaybeOwned<'a, T: 'a> {
+  Borrowed(&'a mut T),
+  Owned(T)
+}
+
+impl<'a, T> Deref for MaybeOwned<'a, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    match *self {
+      MaybeOwned::Borrowed(ref value) => value,
+      MaybeOwned::Owned(ref value) => value
+    }
+  }
+}
+
+impl<'a, T> DerefMut for MaybeOwned<'a, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    match *self {
+      MaybeOwned::Borrowed(ref mut value) => value,
+      MaybeOwned::Owned(ref mut value) => value
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Emission<Channel, Payload> {
+  pub now: Vec<Event<Channel, Payload>>,
+  pub deferred: Vec<Event<Channel, Payload>>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessedEvent<Channel, Payload> {
+  pub seq: u64,
+  pub event: Event<Channel, Payload>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownChannel<Channel>(pub Channel);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusBusy;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueueOverflow<Channel, Payload>(pub Event<Channel, Payload>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnroutedEvent<Channel, Payload>(pub Event<Channel, Payload>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+  Command,
+  Event
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoleMismatch<Channel> {
+  pub channel: Channel,
+  pub expected: Role,
+  pub declared: Role
+}
+
+// Reported whenever max_depth cuts a cascade short. When a channel repeats
+// along the causing chain, path is the first loop and repeats is set;
+// otherwise path is the whole chain that ran out of depth.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleError<Channel> {
+  pub path: Vec<Channel>,
+  pub repeats: bool
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BusError<Channel> {
+  NoListeners(Channel)
+}
+
+#[derive(Clone)]
+pub struct Handle<Channel> {
+  pending_unsubscribes: Rc<RefCell<Vec<Channel>>>,
+  processing: Rc<Cell<bool>>
+}
+
+impl<Channel> Handle<Channel> {
+  pub fn request_unsubscribe_all(&self, channel: Channel) {
+    self.pending_unsubscribes.borrow_mut().push(channel);
+  }
+
+  pub fn is_processing(&self) -> bool {
+    self.processing.get()
+  }
+}
+
+#[derive(Clone)]
+pub struct Forwarder<Channel, Payload> {
+  id: SubscriptionId,
+  pending: Sink<Channel, Payload>
+}
+
+impl<Channel: Hash + Eq + Clone, Payload: Clone> Forwarder<Channel, Payload> {
+  pub fn id(&self) -> SubscriptionId {
+    self.id
+  }
+
+  pub fn pending_len(&self) -> usize {
+    self.pending.borrow().len()
+  }
+
+  pub fn flush_into<Context, E>(&self, other: &mut Pubsub<Context, Channel, Payload, E>) -> usize {
+    let pending: Vec<Event<Channel, Payload>> = self.pending.borrow_mut().drain(..).collect();
+    other.publish_all(pending)
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Metrics<Channel: Hash + Eq> {
+  pub total_events_processed: u64,
+  pub channel_dispatches: HashMap<Channel, u64>
+}
+
+impl<Channel: Hash + Eq> Metrics<Channel> {
+  fn new() -> Metrics<Channel> {
+    Metrics {
+      total_events_processed: 0,
+      channel_dispatches: HashMap::new()
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct TimingStats<Channel: Hash + Eq> {
+  pub total: HashMap<Channel, Duration>,
+  pub max: HashMap<Channel, Duration>
+}
+
+impl<Channel: Hash + Eq + Clone> TimingStats<Channel> {
+  fn new() -> TimingStats<Channel> {
+    TimingStats {
+      total: HashMap::new(),
+      max: HashMap::new()
+    }
+  }
+
+  fn record(&mut self, channel: &Channel, elapsed: Duration) {
+    *self.total.entry(channel.clone()).or_insert(Duration::ZERO) += elapsed;
+    let max = self.max.entry(channel.clone()).or_insert(Duration::ZERO);
+    if elapsed > *max {
+      *max = elapsed;
+    }
+  }
+}
+
+pub type Listener<Context, Channel, Payload> = fn(&mut Context, Payload) -> Vec<Event<Channel, Payload>>;
+pub type ChannelListener<Context, Channel, Payload> = fn(&mut Context, &Channel, Payload) -> Vec<Event<Channel, Payload>>;
+pub type GlobalListener<Context, Channel, Payload> = fn(&mut Context, Channel, Payload) -> Vec<Event<Channel, Payload>>;
+pub type RcListener<Context, Channel, Payload> = fn(&mut Context, Rc<Payload>) -> Vec<Event<Channel, Payload>>;
+pub type OptListener<Context, Channel, Payload> = fn(&mut Context, Payload) -> Option<Event<Channel, Payload>>;
+pub type ConsumingListener<Context, Channel, Payload> = fn(&mut Context, Payload) -> (Vec<Event<Channel, Payload>>, bool);
+pub type DeferringListener<Context, Channel, Payload> = fn(&mut Context, Payload) -> Emission<Channel, Payload>;
+pub type DepthAwareListener<Context, Channel, Payload> = fn(&mut Context, Payload, usize) -> Vec<Event<Channel, Payload>>;
+pub type IndexedListener<Context, Channel, Payload> = fn(&mut Context, Payload, usize) -> Vec<Event<Channel, Payload>>;
+pub type QueueAwareListener<Context, Channel, Payload> = fn(&mut Context, Payload, &mut EventQueue<Channel, Payload>);
+pub type TryListener<Context, Channel, Payload, E> = fn(&mut Context, Payload) -> Result<Vec<Event<Channel, Payload>>, E>;
+pub type DeadLetterHandler<Context, Channel, Payload> = fn(&mut Context, &Channel, &Payload);
+pub type Hook<Channel, Payload> = fn(&Channel, &Payload);
+pub type PanicHandler<Channel> = fn(&Channel);
+pub type Middleware<Channel, Payload> = fn(&Channel, Payload) -> Payload;
+pub type Filter<Payload> = fn(&Payload) -> bool;
+pub type AnyPayload = Rc<dyn Any>;
+pub type TypedListener<Context, Channel, T> = fn(&mut Context, T) -> Vec<Event<Channel, AnyPayload>>;
+pub type BoxedListener<'a, Context, Channel, Payload> = Box<dyn FnMut(&mut Context, Payload) -> Vec<Event<Channel, Payload>> + 'a>;
+
+type LevelOf<Payload> = fn(&Payload) -> u8;
+type EventEq<Channel, Payload> = fn(&Event<Channel, Payload>, &Event<Channel, Payload>) -> bool;
+type Sink<Channel, Payload> = Rc<RefCell<Vec<Event<Channel, Payload>>>>;
+type Callback<'a, Context, Channel, Payload, E> = Rc<RefCell<dyn FnMut(&mut Context, &mut Invocation<Channel, Payload>, Rc<Payload>) -> Result<Vec<Event<Channel, Payload>>, E> + 'a>>;
+
+fn callback<'a, Context, Channel, Payload, E, F>(f: F) -> Callback<'a, Context, Channel, Payload, E>
+  where F: FnMut(&mut Context, &mut Invocation<Channel, Payload>, Rc<Payload>) -> Result<Vec<Event<Channel, Payload>>, E> + 'a {
+  Rc::new(RefCell::new(f))
+}
+
+// What a callback learns about the dispatch it runs in. It is built by the
+// bus doing the dispatch, so copies of a subscription in forked or merged
+// buses report that bus's state. forward is only set on the bus whose
+// forward() created the subscription; copies elsewhere forward nothing.
+struct Invocation<'c, Channel, Payload> {
+  channel: &'c Channel,
+  depth: usize,
+  index: usize,
+  deferred: &'c mut Vec<Event<Channel, Payload>>,
+  forward: Option<&'c RefCell<Vec<Event<Channel, Payload>>>>
+}
+
+// The chain of events that led to a queued event, newest first. Only kept
+// when max_depth is set, for reporting cycles.
+struct Ancestry<Channel> {
+  channel: Channel,
+  parent: Lineage<Channel>
+}
+
+type Lineage<Channel> = Option<Rc<Ancestry<Channel>>>;
+
+// A request waiting on its reply channel. The first event its cascade emits
+// there is kept.
+struct Reply<Channel, Payload> {
+  channel: Channel,
+  payload: RefCell<Option<Payload>>
+}
+
+impl<Channel: PartialEq, Payload: Clone> Reply<Channel, Payload> {
+  fn offer(&self, channel: &Channel, payload: &Payload) {
+    let mut slot = self.payload.borrow_mut();
+    if slot.is_none() && *channel == self.channel {
+      *slot = Some(payload.clone());
+    }
+  }
+}
+
+// What the bus keeps beside each queued event: the chain that caused it, the
+// request it belongs to and how many events deep in its cascade it sits.
+// Events a listener emits inherit the first two, one level deeper.
+#[derive(Clone)]
+struct Trace<Channel, Payload> {
+  ancestry: Lineage<Channel>,
+  reply: Option<Rc<Reply<Channel, Payload>>>,
+  depth: usize
+}
+
+impl<Channel, Payload> Trace<Channel, Payload> {
+  fn root() -> Trace<Channel, Payload> {
+    Trace { ancestry: None, reply: None, depth: 0 }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GroupId(pub u32);
+
+#[derive(Clone)]
+struct Debounce {
+  min_interval: Duration,
+  last: Cell<Option<Instant>>
+}
+
+impl Debounce {
+  fn ready(&self) -> bool {
+    let now = Instant::now();
+    if self.last.get().is_some_and(|last| now.duration_since(last) < self.min_interval) {
+      return false;
+    }
+    self.last.set(Some(now));
+    true
+  }
+}
+
+struct Subscription<'a, Context, Channel, Payload, E> {
+  id: SubscriptionId,
+  callback: Callback<'a, Context, Channel, Payload, E>,
+  pointer: Option<usize>,
+  once: bool,
+  priority: i32,
+  filter: Option<Filter<Payload>>,
+  tag: Option<String>,
+  group: Option<GroupId>,
+  alive: Option<Rc<dyn Fn() -> bool + 'a>>,
+  stop: Option<Rc<Cell<bool>>>,
+  queue_listener: Option<QueueAwareListener<Context, Channel, Payload>>,
+  min_level: Option<(u8, LevelOf<Payload>)>,
+  debounce: Option<Debounce>
+}
+
+impl<'a, Context, Channel, Payload, E> Clone for Subscription<'a, Context, Channel, Payload, E> {
+  fn clone(&self) -> Subscription<'a, Context, Channel, Payload, E> {
+    Subscription {
+      id: self.id,
+      callback: self.callback.clone(),
+      pointer: self.pointer,
+      once: self.once,
+      priority: self.priority,
+      filter: self.filter,
+      tag: self.tag.clone(),
+      group: self.group,
+      alive: self.alive.clone(),
+      stop: self.stop.clone(),
+      queue_listener: self.queue_listener,
+      min_level: self.min_level,
+      debounce: self.debounce.clone()
+    }
+  }
+}
+
+impl<'a, Context, Channel, Payload, E> Subscription<'a, Context, Channel, Payload, E> {
+  fn new(callback: Callback<'a, Context, Channel, Payload, E>, pointer: Option<usize>) -> Subscription<'a, Context, Channel, Payload, E> {
+    Subscription {
+      id: SubscriptionId(0),
+      callback,
+      pointer,
+      once: false,
+      priority: 0,
+      filter: None,
+      tag: None,
+      group: None,
+      alive: None,
+      stop: None,
+      queue_listener: None,
+      min_level: None,
+      debounce: None
+    }
+  }
+
+  fn is_alive(&self) -> bool {
+    self.alive.as_ref().is_none_or(|alive| alive())
+  }
+
+  fn accepts(&self, payload: &Payload) -> bool {
+    self.filter.is_none_or(|filter| filter(payload)) && self.min_level.is_none_or(|(min_level, level)| level(payload) >= min_level)
+      && self.debounce.as_ref().is_none_or(|debounce| debounce.ready())
+  }
+
+  fn from_listener(listener: Listener<Context, Channel, Payload>) -> Subscription<'a, Context, Channel, Payload, E> where Context: 'a, Channel: 'a, Payload: Clone + 'a {
+    Subscription::new(callback(move |context, _call, payload: Rc<Payload>| Ok(listener(context, (*payload).clone()))), Some(listener as usize))
+  }
+}
+
+fn live_count<Context, Channel, Payload, E>(existing_vec: &[Subscription<'_, Context, Channel, Payload, E>]) -> usize {
+  existing_vec.iter().filter(|s| s.is_alive()).count()
+}
+
+fn insert_by_priority<'a, Context, Channel, Payload, E>(existing_vec: &mut Vec<Subscription<'a, Context, Channel, Payload, E>>, subscription: Subscription<'a, Context, Channel, Payload, E>) {
+  let index = existing_vec.iter().position(|s| s.priority < subscription.priority).unwrap_or(existing_vec.len());
+  existing_vec.insert(index, subscription);
+}
+
+fn normalized<Channel>(normalizer: Option<fn(Channel) -> Channel>, channel: Channel) -> Channel {
+  match normalizer {
+    Some(normalizer) => normalizer(channel),
+    None => channel
+  }
+}
+
+type Matcher<'a, Channel> = Rc<dyn Fn(&Channel, Option<fn(Channel) -> Channel>) -> bool + 'a>;
+
+// A pattern keeps the prefix or range it was subscribed with and normalizes it
+// when matching, so it follows the bus's current normalizer. key is the
+// channel it is listed under: the prefix, or the start of the range.
+struct PatternSubscription<'a, Context, Channel, Payload, E> {
+  key: Channel,
+  matches: Matcher<'a, Channel>,
+  subscription: Subscription<'a, Context, Channel, Payload, E>
+}
+
+impl<'a, Context, Channel: Clone, Payload, E> Clone for PatternSubscription<'a, Context, Channel, Payload, E> {
+  fn clone(&self) -> PatternSubscription<'a, Context, Channel, Payload, E> {
+    PatternSubscription {
+      key: self.key.clone(),
+      matches: self.matches.clone(),
+      subscription: self.subscription.clone()
+    }
+  }
+}
+
+pub trait ChannelMatcher {
+  fn matches(&self, channel: &Self) -> bool;
+}
+
+impl ChannelMatcher for String {
+  fn matches(&self, channel: &String) -> bool {
+    channel.starts_with(self.as_str())
+  }
+}
+
+pub trait Leveled {
+  fn level(&self) -> u8;
+}
+
+pub struct SubscriptionSnapshot<'a, Context, Channel, Payload, E = ()> {
+  listeners: HashMap<Channel, Vec<Subscription<'a, Context, Channel, Payload, E>>>,
+  channel_order: Vec<Channel>
+}
+
+pub struct Pubsub<'a, Context:'a, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a = ()> {
+  pub context: MaybeOwned<'a, Context>,
+  listeners: HashMap<Channel, Vec<Subscription<'a, Context, Channel, Payload, E>>>,
+  global_listeners: Vec<(i32, GlobalListener<Context, Channel, Payload>)>,
+  event_queue: EventQueue<Channel, Payload>,
+  max_depth: usize,
+  next_id: u64,
+  history: VecDeque<ProcessedEvent<Channel, Payload>>,
+  history_capacity: usize,
+  collected: Option<Vec<ProcessedEvent<Channel, Payload>>>,
+  handle: Handle<Channel>,
+  metrics: Metrics<Channel>,
+  metrics_enabled: bool,
+  timing: TimingStats<Channel>,
+  timing_enabled: bool,
+  dead_letter: Option<DeadLetterHandler<Context, Channel, Payload>>,
+  known_channels: HashSet<Channel>,
+  listener_hook: Option<(Hook<Channel, Payload>, Hook<Channel, Payload>)>,
+  max_queue: usize,
+  overflow: Option<QueueOverflow<Channel, Payload>>,
+  dispatcher: Box<dyn Installed<'a, Channel, Payload> + 'a>,
+  strict: bool,
+  unrouted: Option<UnroutedEvent<Channel, Payload>>,
+  channel_order: Vec<Channel>,
+  panic_handler: Option<PanicHandler<Channel>>,
+  middleware: Vec<Middleware<Channel, Payload>>,
+  paused: bool,
+  seq: u64,
+  pattern_listeners: Vec<PatternSubscription<'a, Context, Channel, Payload, E>>,
+  coalesce: Option<EventEq<Channel, Payload>>,
+  watermark: Option<(usize, fn(usize))>,
+  above_watermark: bool,
+  deferred: Vec<Event<Channel, Payload>>,
+  forwarders: HashMap<SubscriptionId, Sink<Channel, Payload>>,
+  last_error: Option<BusError<Channel>>,
+  listener_error: Option<E>,
+  cycle: Option<CycleError<Channel>>,
+  on_idle: Option<fn(&mut Context)>,
+  roles: HashMap<Channel, Role>,
+  disabled_groups: HashSet<GroupId>,
+  normalizer: Option<fn(Channel) -> Channel>
+}
+
+pub type PubsubOwned<Context, Channel, Payload, E = ()> = Pubsub<'static, Context, Channel, Payload, E>;
+
+// The guard holds the bus mutably borrowed for as long as it lives, so the bus
+// is only reachable through the guard until it is dropped.
+pub struct ScopedSubscription<'g, 'a: 'g, Context: 'a, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a = ()> {
+  pubsub: &'g mut Pubsub<'a, Context, Channel, Payload, E>,
+  id: SubscriptionId
+}
+
+impl<'g, 'a, Context, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a> Deref for ScopedSubscription<'g, 'a, Context, Channel, Payload, E> {
+  type Target = Pubsub<'a, Context, Channel, Payload, E>;
+
+  fn deref(&self) -> &Pubsub<'a, Context, Channel, Payload, E> {
+    self.pubsub
+  }
+}
+
+impl<'g, 'a, Context, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a> DerefMut for ScopedSubscription<'g, 'a, Context, Channel, Payload, E> {
+  fn deref_mut(&mut self) -> &mut Pubsub<'a, Context, Channel, Payload, E> {
+    self.pubsub
+  }
+}
+
+impl<'g, 'a, Context, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a> Drop for ScopedSubscription<'g, 'a, Context, Channel, Payload, E> {
+  fn drop(&mut self) {
+    self.pubsub.unsubscribe_by_id(self.id);
+  }
+}
+
+impl<'a, Context, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a> Pubsub<'a, Context, Channel, Payload, E> {
+  pub fn new(context: &'a mut Context) -> Pubsub<'a, Context, Channel, Payload, E> {
+    Pubsub::with_context(MaybeOwned::Borrowed(context))
+  }
+
+  pub fn owned(context: Context) -> Pubsub<'a, Context, Channel, Payload, E> {
+    Pubsub::with_context(MaybeOwned::Owned(context))
+  }
+
+  pub fn with_max_depth(context: &'a mut Context, max_depth: usize) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.max_depth = max_depth;
+    pubsub
+  }
+
+  pub fn from_listeners(context: &'a mut Context, map: HashMap<Channel, Vec<Listener<Context, Channel, Payload>>>) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    for (channel, listeners) in map {
+      for listener in listeners {
+        pubsub.subscribe(channel.clone(), listener);
+      }
+    }
+    pubsub
+  }
+
+  pub fn with_history(context: &'a mut Context, capacity: usize) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.history_capacity = capacity;
+    pubsub
+  }
+
+  pub fn with_metrics(context: &'a mut Context) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.metrics_enabled = true;
+    pubsub
+  }
+
+  pub fn with_timing(context: &'a mut Context) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.timing_enabled = true;
+    pubsub
+  }
+
+  pub fn with_max_queue(context: &'a mut Context, cap: usize) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.max_queue = cap;
+    pubsub
+  }
+
+  pub fn with_strict(context: &'a mut Context) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.strict = true;
+    pubsub
+  }
+
+  pub fn with_coalescing(context: &'a mut Context) -> Pubsub<'a, Context, Channel, Payload, E> where Payload: Eq {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.coalesce = Some(|pending, event| pending == event);
+    pubsub
+  }
+
+  pub fn with_channel_normalizer(context: &'a mut Context, normalizer: fn(Channel) -> Channel) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.normalizer = Some(normalizer);
+    pubsub
+  }
+
+  pub fn with_dispatcher<D: Dispatcher<Channel, Payload> + Clone + 'a>(context: &'a mut Context, dispatcher: D) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::new(context);
+    pubsub.set_dispatcher(dispatcher);
+    pubsub
+  }
+
+  fn fork(&self, context: MaybeOwned<'a, Context>) -> Pubsub<'a, Context, Channel, Payload, E> {
+    let mut pubsub = Pubsub::with_context(context);
+    pubsub.listeners = self.listeners.clone();
+    pubsub.channel_order = self.channel_order.clone();
+    pubsub.global_listeners = self.global_listeners.clone();
+    pubsub.max_depth = self.max_depth;
+    pubsub.next_id = self.next_id;
+    pubsub.history_capacity = self.history_capacity;
+    pubsub.metrics_enabled = self.metrics_enabled;
+    pubsub.timing_enabled = self.timing_enabled;
+    pubsub.dead_letter = self.dead_letter;
+    pubsub.known_channels = self.known_channels.clone();
+    pubsub.listener_hook = self.listener_hook;
+    pubsub.max_queue = self.max_queue;
+    pubsub.dispatcher = self.dispatcher.fork();
+    pubsub.strict = self.strict;
+    pubsub.panic_handler = self.panic_handler;
+    pubsub.middleware = self.middleware.clone();
+    pubsub.pattern_listeners = self.pattern_listeners.clone();
+    pubsub.coalesce = self.coalesce;
+    pubsub.watermark = self.watermark;
+    pubsub.on_idle = self.on_idle;
+    pubsub.roles = self.roles.clone();
+    pubsub.disabled_groups = self.disabled_groups.clone();
+    pubsub.normalizer = self.normalizer;
+    pubsub
+  }
+
+  fn with_context(context: MaybeOwned<'a, Context>) -> Pubsub<'a, Context, Channel, Payload, E> {
+    Pubsub {
+      context,
+      listeners: HashMap::new(),
+      global_listeners: Vec::new(),
+      event_queue: EventQueue::new(),
+      max_depth: usize::MAX,
+      next_id: 0,
+      history: VecDeque::new(),
+      history_capacity: 0,
+      collected: None,
+      handle: Handle { pending_unsubscribes: Rc::new(RefCell::new(Vec::new())), processing: Rc::new(Cell::new(false)) },
+      metrics: Metrics::new(),
+      metrics_enabled: false,
+      timing: TimingStats::new(),
+      timing_enabled: false,
+      dead_letter: None,
+      known_channels: HashSet::new(),
+      listener_hook: None,
+      max_queue: usize::MAX,
+      overflow: None,
+      dispatcher: Box::new(Fifo),
+      strict: false,
+      unrouted: None,
+      channel_order: Vec::new(),
+      panic_handler: None,
+      middleware: Vec::new(),
+      paused: false,
+      seq: 0,
+      pattern_listeners: Vec::new(),
+      coalesce: None,
+      watermark: None,
+      above_watermark: false,
+      deferred: Vec::new(),
+      forwarders: HashMap::new(),
+      last_error: None,
+      listener_error: None,
+      cycle: None,
+      on_idle: None,
+      roles: HashMap::new(),
+      disabled_groups: HashSet::new(),
+      normalizer: None
+    }
+  }
+
+  pub fn context(&self) -> &Context {
+    &self.context
+  }
+
+  pub fn context_mut(&mut self) -> &mut Context {
+    &mut self.context
+  }
+
+  pub fn publish(&mut self, event: Event<Channel, Payload>) -> usize {
+    self.enqueue(event);
+    if self.is_processing() {
+      return 0;
+    }
+    self.drain()
+  }
+
+  pub fn try_publish(&mut self, event: Event<Channel, Payload>) -> Result<usize, BusBusy> {
+    if self.is_processing() {
+      return Err(BusBusy);
+    }
+    Ok(self.publish(event))
+  }
+
+  pub fn publish_ref(&mut self, event: &Event<Channel, Payload>) -> usize {
+    self.publish(event.clone())
+  }
+
+  pub fn publish_try(&mut self, event: Event<Channel, Payload>) -> Result<usize, E> {
+    self.enqueue(event);
+    let mut invoked = 0;
+    self.process_queue(&mut invoked, usize::MAX, &mut |_| {}).map(|_| invoked)
+  }
+
+  pub fn publish_collect(&mut self, event: Event<Channel, Payload>) -> Vec<ProcessedEvent<Channel, Payload>> {
+    self.collected = Some(Vec::new());
+    self.publish(event);
+    self.collected.take().unwrap_or_default()
+  }
+
+  pub fn publish_until(&mut self, event: Event<Channel, Payload>, target: fn(&Channel, &Payload) -> bool) -> bool {
+    self.publish_collect(event).iter().any(|processed| target(&processed.event.channel, &processed.event.payload))
+  }
+
+  pub fn request(&mut self, event: Event<Channel, Payload>, reply_channel: Channel) -> Option<Payload> {
+    let reply = Rc::new(Reply { channel: self.normalize(reply_channel), payload: RefCell::new(None) });
+    self.push_event(event, Trace { ancestry: None, reply: Some(reply.clone()), depth: 0 });
+    if !self.is_processing() {
+      self.drain();
+    }
+    reply.payload.take()
+  }
+
+  pub fn clone_config_with(&self, new_context: &'a mut Context) -> Pubsub<'a, Context, Channel, Payload, E> {
+    self.fork(MaybeOwned::Borrowed(new_context))
+  }
+
+  // The dry run dispatches on a fork, which forwards nothing. Unsubscribes
+  // requested through a captured Handle are discarded when it finishes. Any
+  // other state a closure listener captured is still shared with this bus.
+  pub fn simulate(&self, event: Event<Channel, Payload>) -> Context where Context: Clone {
+    let requested = self.handle.pending_unsubscribes.borrow().len();
+    let mut pubsub = self.fork(MaybeOwned::Owned(self.context().clone()));
+    pubsub.publish(event);
+    self.handle.pending_unsubscribes.borrow_mut().truncate(requested);
+    match pubsub.context {
+      MaybeOwned::Owned(context) => context,
+      MaybeOwned::Borrowed(context) => context.clone()
+    }
+  }
+
+  pub fn publish_checked(&mut self, event: Event<Channel, Payload>) -> Result<usize, UnknownChannel<Channel>> {
+    if !self.known_channels.contains(&self.normalize(event.channel.clone())) {
+      return Err(UnknownChannel(event.channel));
+    }
+    Ok(self.publish(event))
+  }
+
+  pub fn publish_bounded(&mut self, event: Event<Channel, Payload>) -> Result<usize, QueueOverflow<Channel, Payload>> {
+    self.overflow = None;
+    let invoked = self.publish(event);
+    match self.overflow.take() {
+      Some(overflow) => Err(overflow),
+      None => Ok(invoked)
+    }
+  }
+
+  pub fn publish_cycle_checked(&mut self, event: Event<Channel, Payload>) -> Result<usize, CycleError<Channel>> {
+    let invoked = self.publish(event);
+    match self.cycle.take() {
+      Some(cycle) => Err(cycle),
+      None => Ok(invoked)
+    }
+  }
+
+  pub fn publish_command(&mut self, event: Event<Channel, Payload>) -> Result<usize, RoleMismatch<Channel>> {
+    self.publish_as(Role::Command, event)
+  }
+
+  pub fn publish_event(&mut self, event: Event<Channel, Payload>) -> Result<usize, RoleMismatch<Channel>> {
+    self.publish_as(Role::Event, event)
+  }
+
+  fn publish_as(&mut self, expected: Role, event: Event<Channel, Payload>) -> Result<usize, RoleMismatch<Channel>> {
+    match self.roles.get(&self.normalize(event.channel.clone())) {
+      Some(&declared) if declared != expected => Err(RoleMismatch { channel: event.channel, expected, declared }),
+      _ => Ok(self.publish(event))
+    }
+  }
+
+  pub fn publish_strict(&mut self, event: Event<Channel, Payload>) -> Result<usize, UnroutedEvent<Channel, Payload>> {
+    let invoked = self.publish(event);
+    match self.unrouted.take() {
+      Some(unrouted) => Err(unrouted),
+      None => Ok(invoked)
+    }
+  }
+
+  pub fn register_channel(&mut self, channel: Channel) {
+    let channel = self.normalize(channel);
+    self.known_channels.insert(channel);
+  }
+
+  pub fn publish_routable(&mut self, payload: Payload) -> usize where Payload: Routable<Channel> {
+    self.publish(builder::route(payload))
+  }
+
+  pub fn publish_all(&mut self, events: Vec<Event<Channel, Payload>>) -> usize {
+    for event in events {
+      self.enqueue(event);
+    }
+    self.drain()
+  }
+
+  pub fn publish_slice(&mut self, events: &[Event<Channel, Payload>]) -> usize {
+    self.publish_all(events.to_vec())
+  }
+
+  pub fn pump<I: IntoIterator<Item = Event<Channel, Payload>>>(&mut self, source: I) -> usize {
+    source.into_iter().map(|event| self.publish(event)).sum()
+  }
+
+  pub fn flush_deferred(&mut self) -> usize {
+    let deferred = std::mem::take(&mut self.deferred);
+    self.publish_all(deferred)
+  }
+
+  pub fn deferred_len(&self) -> usize {
+    self.deferred.len()
+  }
+
+  pub fn run_from(&mut self, rx: Receiver<Event<Channel, Payload>>) -> usize {
+    self.pump(rx)
+  }
+
+  pub fn replay(&mut self) -> usize {
+    let events: Vec<Event<Channel, Payload>> = self.history.iter().map(|processed| processed.event.clone()).collect();
+    let capacity = self.history_capacity;
+    self.history_capacity = 0;
+    let invoked = self.publish_all(events);
+    self.history_capacity = capacity;
+    invoked
+  }
+
+  pub fn subscribe(&mut self, channel: Channel, listener: Listener<Context, Channel, Payload>) {
+    self.add_subscription(channel, Subscription::from_listener(listener));
+  }
+
+  pub fn subscribe_count(&mut self, channel: Channel, listener: Listener<Context, Channel, Payload>) -> usize {
+    self.subscribe(channel.clone(), listener);
+    self.listener_count(&channel)
+  }
+
+  pub fn subscribe_many(&mut self, channels: Vec<Channel>, listener: Listener<Context, Channel, Payload>) {
+    for channel in channels {
+      self.subscribe(channel, listener);
+    }
+  }
+
+  pub fn subscribe_unique(&mut self, channel: Channel, listener: Listener<Context, Channel, Payload>) -> bool {
+    if self.contains_listener(&channel, listener) {
+      return false;
+    }
+    self.subscribe(channel, listener);
+    true
+  }
+
+  pub fn contains_listener(&self, channel: &Channel, listener: Listener<Context, Channel, Payload>) -> bool {
+    let pointer = Some(listener as usize);
+    self.listeners.get(&self.normalize(channel.clone())).is_some_and(|v| v.iter().any(|s| s.pointer == pointer))
+  }
+
+  pub fn subscribe_with_id(&mut self, channel: Channel, listener: Listener<Context, Channel, Payload>) -> SubscriptionId {
+    self.add_subscription(channel, Subscription::from_listener(listener))
+  }
+
+  pub fn subscribe_scoped<'g>(&'g mut self, channel: Channel, listener: Listener<Context, Channel, Payload>) -> ScopedSubscription<'g, 'a, Context, Channel, Payload, E> {
+    let id = self.subscribe_with_id(channel, listener);
+    ScopedSubscription { pubsub: self, id }
+  }
+
+  pub fn subscribe_with_priority(&mut self, channel: Channel, priority: i32, listener: Listener<Context, Channel, Payload>) {
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.priority = priority;
+    self.add_subscription(channel, subscription);
+  }
+
+  pub fn subscribe_filtered(&mut self, channel: Channel, predicate: Filter<Payload>, listener: Listener<Context, Channel, Payload>) {
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.filter = Some(predicate);
+    self.add_subscription(channel, subscription);
+  }
+
+  pub fn subscribe_min_level(&mut self, channel: Channel, min_level: u8, listener: Listener<Context, Channel, Payload>) where Payload: Leveled {
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.min_level = Some((min_level, Payload::level));
+    self.add_subscription(channel, subscription);
+  }
+
+  pub fn subscribe_with_role(&mut self, channel: Channel, role: Role, listener: Listener<Context, Channel, Payload>) {
+    self.roles.insert(self.normalize(channel.clone()), role);
+    self.subscribe(channel, listener);
+  }
+
+  pub fn subscribe_tagged(&mut self, channel: Channel, tag: &str, listener: Listener<Context, Channel, Payload>) -> SubscriptionId {
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.tag = Some(tag.to_string());
+    self.add_subscription(channel, subscription)
+  }
+
+  pub fn subscribe_debounced(&mut self, channel: Channel, min_interval: Duration, listener: Listener<Context, Channel, Payload>) -> SubscriptionId {
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.debounce = Some(Debounce { min_interval, last: Cell::new(None) });
+    self.add_subscription(channel, subscription)
+  }
+
+  pub fn subscribe_in_group(&mut self, channel: Channel, group: GroupId, listener: Listener<Context, Channel, Payload>) -> SubscriptionId {
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.group = Some(group);
+    self.add_subscription(channel, subscription)
+  }
+
+  pub fn enable_group(&mut self, group: GroupId) {
+    self.disabled_groups.remove(&group);
+  }
+
+  pub fn disable_group(&mut self, group: GroupId) {
+    self.disabled_groups.insert(group);
+  }
+
+  pub fn subscribe_weak<T: 'a>(&mut self, channel: Channel, weak: Weak<RefCell<T>>, listener: Listener<Context, Channel, Payload>) {
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.alive = Some(Rc::new(move || weak.strong_count() > 0));
+    self.add_subscription(channel, subscription);
+  }
+
+  pub fn subscribe_once(&mut self, channel: Channel, listener: Listener<Context, Channel, Payload>) {
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.once = true;
+    self.add_subscription(channel, subscription);
+  }
+
+  pub fn subscribe_fn(&mut self, channel: Channel, mut listener: BoxedListener<'a, Context, Channel, Payload>) {
+    self.add_subscription(channel, Subscription::new(callback(move |context, _call, payload: Rc<Payload>| Ok(listener(context, (*payload).clone()))), None));
+  }
+
+  pub fn subscribe_try(&mut self, channel: Channel, listener: TryListener<Context, Channel, Payload, E>) {
+    self.add_subscription(channel, Subscription::new(callback(move |context, _call, payload: Rc<Payload>| listener(context, (*payload).clone())), Some(listener as usize)));
+  }
+
+  pub fn subscribe_channel_aware(&mut self, channel: Channel, listener: ChannelListener<Context, Channel, Payload>) {
+    self.add_subscription(channel, Subscription::new(callback(move |context, call, payload: Rc<Payload>| Ok(listener(context, call.channel, (*payload).clone()))), Some(listener as usize)));
+  }
+
+  pub fn subscribe_rc(&mut self, channel: Channel, listener: RcListener<Context, Channel, Payload>) {
+    self.add_subscription(channel, Subscription::new(callback(move |context, _call, payload| Ok(listener(context, payload))), Some(listener as usize)));
+  }
+
+  pub fn subscribe_opt(&mut self, channel: Channel, listener: OptListener<Context, Channel, Payload>) {
+    self.add_subscription(channel, Subscription::new(callback(move |context, _call, payload: Rc<Payload>| Ok(listener(context, (*payload).clone()).into_iter().collect())), Some(listener as usize)));
+  }
+
+  pub fn subscribe_consuming(&mut self, channel: Channel, listener: ConsumingListener<Context, Channel, Payload>) {
+    let stop = Rc::new(Cell::new(false));
+    let signal = stop.clone();
+    let mut subscription = Subscription::new(callback(move |context, _call, payload: Rc<Payload>| {
+      let (tail, stop) = listener(context, (*payload).clone());
+      signal.set(stop);
+      Ok(tail)
+    }), Some(listener as usize));
+    subscription.stop = Some(stop);
+    self.add_subscription(channel, subscription);
+  }
+
+  pub fn subscribe_prefix(&mut self, prefix: Channel, listener: Listener<Context, Channel, Payload>) -> SubscriptionId where Channel: ChannelMatcher {
+    let key = prefix.clone();
+    self.add_pattern_subscription(key, Rc::new(move |channel, normalizer| normalized(normalizer, prefix.clone()).matches(channel)), listener)
+  }
+
+  pub fn subscribe_range(&mut self, range: Range<Channel>, listener: Listener<Context, Channel, Payload>) -> SubscriptionId where Channel: PartialOrd {
+    let key = range.start.clone();
+    self.add_pattern_subscription(key, Rc::new(move |channel, normalizer| {
+      (normalized(normalizer, range.start.clone())..normalized(normalizer, range.end.clone())).contains(channel)
+    }), listener)
+  }
+
+  pub fn subscribe_deferring(&mut self, channel: Channel, listener: DeferringListener<Context, Channel, Payload>) {
+    self.add_subscription(channel, Subscription::new(callback(move |context, call, payload: Rc<Payload>| {
+      let emission = listener(context, (*payload).clone());
+      call.deferred.extend(emission.deferred);
+      Ok(emission.now)
+    }), Some(listener as usize)));
+  }
+
+  pub fn subscribe_depth_aware(&mut self, channel: Channel, listener: DepthAwareListener<Context, Channel, Payload>) {
+    self.add_subscription(channel, Subscription::new(callback(move |context, call, payload: Rc<Payload>| Ok(listener(context, (*payload).clone(), call.depth))), Some(listener as usize)));
+  }
+
+  pub fn subscribe_indexed(&mut self, channel: Channel, listener: IndexedListener<Context, Channel, Payload>) {
+    self.add_subscription(channel, Subscription::new(callback(move |context, call, payload: Rc<Payload>| Ok(listener(context, (*payload).clone(), call.index))), Some(listener as usize)));
+  }
+
+  pub fn subscribe_queue_aware(&mut self, channel: Channel, listener: QueueAwareListener<Context, Channel, Payload>) {
+    let mut subscription = Subscription::new(callback(|_context, _call, _payload| Ok(Vec::new())), Some(listener as usize));
+    subscription.queue_listener = Some(listener);
+    self.add_subscription(channel, subscription);
+  }
+
+  pub fn forward(&mut self, channel: Channel) -> Forwarder<Channel, Payload> {
+    let id = self.add_subscription(channel, Subscription::new(callback(|_context, call: &mut Invocation<Channel, Payload>, payload: Rc<Payload>| {
+      if let Some(sink) = call.forward {
+        sink.borrow_mut().push(Event { channel: call.channel.clone(), payload: (*payload).clone() });
+      }
+      Ok(Vec::new())
+    }), None));
+    let pending = Rc::new(RefCell::new(Vec::new()));
+    self.forwarders.insert(id, pending.clone());
+    Forwarder { id, pending }
+  }
+
+  pub fn subscribe_fanout(&mut self, channel: Channel, targets: Vec<Channel>) {
+    self.add_subscription(channel, Subscription::new(callback(move |_context, _call, payload: Rc<Payload>| {
+      Ok(targets.iter().map(|target| Event { channel: target.clone(), payload: (*payload).clone() }).collect())
+    }), None));
+  }
+
+  pub fn subscribe_all(&mut self, listener: GlobalListener<Context, Channel, Payload>) {
+    self.subscribe_all_ordered(0, listener);
+  }
+
+  pub fn subscribe_all_ordered(&mut self, key: i32, listener: GlobalListener<Context, Channel, Payload>) {
+    let index = self.global_listeners.iter().position(|&(existing, _)| existing > key).unwrap_or(self.global_listeners.len());
+    self.global_listeners.insert(index, (key, listener));
+  }
+
+  pub fn set_max_depth(&mut self, max_depth: usize) {
+    self.max_depth = max_depth;
+  }
+
+  pub fn set_history_capacity(&mut self, capacity: usize) {
+    self.history_capacity = capacity;
+    while self.history.len() > capacity {
+      self.history.pop_front();
+    }
+  }
+
+  pub fn set_metrics(&mut self, enabled: bool) {
+    self.metrics_enabled = enabled;
+  }
+
+  pub fn set_timing(&mut self, enabled: bool) {
+    self.timing_enabled = enabled;
+  }
+
+  pub fn set_max_queue(&mut self, cap: usize) {
+    self.max_queue = cap;
+  }
+
+  pub fn set_strict(&mut self, enabled: bool) {
+    self.strict = enabled;
+  }
+
+  pub fn set_coalescing(&mut self, enabled: bool) where Payload: Eq {
+    self.coalesce = if enabled { Some(|pending, event| pending == event) } else { None };
+  }
+
+  // Channels already subscribed, registered or given a role are re-keyed under
+  // the new normalizer, merging any that now collide. Prefix and range
+  // subscriptions normalize their pattern when matching, so they follow too.
+  pub fn set_channel_normalizer(&mut self, normalizer: fn(Channel) -> Channel) {
+    self.normalizer = Some(normalizer);
+    let mut listeners = std::mem::take(&mut self.listeners);
+    let mut moved: Vec<_> = std::mem::take(&mut self.channel_order).into_iter().filter_map(|channel| listeners.remove(&channel).map(|subscriptions| (channel, subscriptions))).collect();
+    moved.extend(listeners);
+    for (channel, subscriptions) in moved {
+      let channel = normalizer(channel);
+      self.track_channel(&channel);
+      let existing_vec = self.listeners.entry(channel).or_default();
+      for subscription in subscriptions {
+        insert_by_priority(existing_vec, subscription);
+      }
+    }
+    self.known_channels = std::mem::take(&mut self.known_channels).into_iter().map(normalizer).collect();
+    self.roles = std::mem::take(&mut self.roles).into_iter().map(|(channel, role)| (normalizer(channel), role)).collect();
+  }
+
+  pub fn set_listener_hook(&mut self, before: Hook<Channel, Payload>, after: Hook<Channel, Payload>) {
+    self.listener_hook = Some((before, after));
+  }
+
+  pub fn set_dispatcher<D: Dispatcher<Channel, Payload> + Clone + 'a>(&mut self, dispatcher: D) {
+    self.dispatcher = Box::new(dispatcher);
+  }
+
+  pub fn set_queue_watermark(&mut self, high: usize, on_high: fn(usize)) {
+    self.watermark = Some((high, on_high));
+    self.above_watermark = false;
+  }
+
+  pub fn add_middleware(&mut self, f: Middleware<Channel, Payload>) {
+    self.middleware.push(f);
+  }
+
+  pub fn on_listener_panic(&mut self, handler: PanicHandler<Channel>) {
+    self.panic_handler = Some(handler);
+  }
+
+  pub fn set_on_idle(&mut self, f: fn(&mut Context)) {
+    self.on_idle = Some(f);
+  }
+
+  pub fn on_dead_letter(&mut self, handler: DeadLetterHandler<Context, Channel, Payload>) {
+    self.dead_letter = Some(handler);
+  }
+
+  pub fn unsubscribe(&mut self, channel: &Channel, listener: Listener<Context, Channel, Payload>) -> bool {
+    let channel = &self.normalize(channel.clone());
+    let removed = match self.listeners.get_mut(channel) {
+      Some(existing_vec) => match existing_vec.iter().position(|s| s.pointer == Some(listener as usize)) {
+        Some(index) => {
+          existing_vec.remove(index);
+          true
+        }
+        None => false
+      },
+      None => false
+    };
+
+    if removed && self.listeners.get(channel).is_some_and(|v| v.is_empty()) {
+      self.listeners.remove(channel);
+    }
+    removed
+  }
+
+  pub fn unsubscribe_by_id(&mut self, id: SubscriptionId) -> bool {
+    let found = self.listeners.iter_mut().find_map(|(channel, existing_vec)| {
+      existing_vec.iter().position(|s| s.id == id).map(|index| {
+        existing_vec.remove(index);
+        (channel.clone(), existing_vec.is_empty())
+      })
+    });
+
+    match found {
+      Some((channel, now_empty)) => {
+        if now_empty {
+          self.listeners.remove(&channel);
+        }
+        self.forwarders.remove(&id);
+        true
+      }
+      None => match self.pattern_listeners.iter().position(|pattern| pattern.subscription.id == id) {
+        Some(index) => {
+          self.pattern_listeners.remove(index);
+          true
+        }
+        None => false
+      }
+    }
+  }
+
+  pub fn handle(&self) -> Handle<Channel> {
+    self.handle.clone()
+  }
+
+  pub fn is_processing(&self) -> bool {
+    self.handle.is_processing()
+  }
+
+  pub fn request_unsubscribe_all(&self, channel: Channel) {
+    self.handle.request_unsubscribe_all(channel);
+  }
+
+  pub fn unsubscribe_matching(&mut self, pred: fn(&str) -> bool) -> usize {
+    let mut removed = 0;
+    for existing_vec in self.listeners.values_mut() {
+      let before = existing_vec.len();
+      existing_vec.retain(|s| !s.tag.as_ref().is_some_and(|tag| pred(tag)));
+      removed += before - existing_vec.len();
+    }
+    self.listeners.retain(|_, existing_vec| !existing_vec.is_empty());
+    removed
+  }
+
+  pub fn list_subscriptions(&self) -> Vec<(Channel, SubscriptionId)> {
+    self.iter_channels()
+      .flat_map(|channel| self.listeners[channel].iter().filter(|s| s.is_alive()).map(move |s| (channel.clone(), s.id)))
+      .chain(self.pattern_listeners.iter().map(|pattern| (self.normalize(pattern.key.clone()), pattern.subscription.id)))
+      .collect()
+  }
+
+  pub fn subscriptions_with_tag(&self, tag: &str) -> Vec<SubscriptionId> {
+    self.iter_channels()
+      .flat_map(|channel| self.listeners[channel].iter())
+      .filter(|s| s.is_alive() && s.tag.as_deref() == Some(tag))
+      .map(|s| s.id)
+      .collect()
+  }
+
+  pub fn unsubscribe_all(&mut self, channel: &Channel) -> usize {
+    match self.listeners.remove(&self.normalize(channel.clone())) {
+      Some(existing_vec) => {
+        self.drop_forwarders();
+        live_count(&existing_vec)
+      },
+      None => 0
+    }
+  }
+
+  pub fn listener_count(&self, channel: &Channel) -> usize {
+    match self.listeners.get(&self.normalize(channel.clone())) {
+      Some(existing_vec) => live_count(existing_vec),
+      None => 0
+    }
+  }
+
+  pub fn channel_count(&self) -> usize {
+    self.listeners.values().filter(|existing_vec| live_count(existing_vec) > 0).count()
+  }
+
+  // Listeners on one channel run in subscription order, and channels are
+  // visited in the order they first gained a listener. Channels whose weak
+  // listeners have all been dropped are skipped.
+  pub fn iter_channels(&self) -> impl Iterator<Item = &Channel> + use<'_, 'a, Context, Channel, Payload, E> {
+    self.channel_order.iter().filter(move |channel| self.listeners.get(*channel).is_some_and(|existing_vec| live_count(existing_vec) > 0))
+  }
+
+  // Each live channel with its listener count, in first-subscribed order.
+  // TODO: the `serde` feature deriving `Serialize` for this manifest is not
+  // in yet; it needs the serde dependency, which this build can't fetch.
+  pub fn manifest(&self) -> Vec<(Channel, usize)> {
+    self.iter_channels().map(|channel| (channel.clone(), self.listener_count(channel))).collect()
+  }
+
+  pub fn set_listeners(&mut self, channel: Channel, listeners: Vec<Listener<Context, Channel, Payload>>) {
+    let channel = self.normalize(channel);
+    if listeners.is_empty() {
+      self.listeners.remove(&channel);
+      self.drop_forwarders();
+      return;
+    }
+    if let Some(existing_vec) = self.listeners.get_mut(&channel) {
+      existing_vec.clear();
+    }
+    self.drop_forwarders();
+    for listener in listeners {
+      self.subscribe(channel.clone(), listener);
+    }
+  }
+
+  pub fn rename_channel(&mut self, from: &Channel, to: Channel) -> bool {
+    let from = &self.normalize(from.clone());
+    let to = self.normalize(to);
+    if *from == to {
+      return self.listeners.contains_key(from);
+    }
+    match self.listeners.remove(from) {
+      Some(subscriptions) => {
+        self.track_channel(&to);
+        let existing_vec = self.listeners.entry(to).or_default();
+        for subscription in subscriptions {
+          insert_by_priority(existing_vec, subscription);
+        }
+        true
+      },
+      None => false
+    }
+  }
+
+  pub fn retain_channels(&mut self, pred: fn(&Channel) -> bool) {
+    self.listeners.retain(|channel, _| pred(channel));
+    self.drop_forwarders();
+  }
+
+  pub fn merge(&mut self, mut other_listeners: SubscriptionSnapshot<'a, Context, Channel, Payload, E>) {
+    for channel in other_listeners.channel_order {
+      if let Some(mut subscriptions) = other_listeners.listeners.remove(&channel) {
+        for subscription in subscriptions.iter_mut() {
+          subscription.id = SubscriptionId(self.next_id);
+          self.next_id += 1;
+        }
+        let channel = self.normalize(channel);
+        self.track_channel(&channel);
+        let existing_vec = self.listeners.entry(channel).or_default();
+        for subscription in subscriptions {
+          insert_by_priority(existing_vec, subscription);
+        }
+      }
+    }
+  }
+
+  pub fn snapshot_subscriptions(&self) -> SubscriptionSnapshot<'a, Context, Channel, Payload, E> {
+    SubscriptionSnapshot {
+      listeners: self.listeners.clone(),
+      channel_order: self.iter_channels().cloned().collect()
+    }
+  }
+
+  pub fn restore_subscriptions(&mut self, snap: SubscriptionSnapshot<'a, Context, Channel, Payload, E>) {
+    self.listeners = snap.listeners;
+    self.channel_order = snap.channel_order;
+    self.drop_forwarders();
+  }
+
+  pub fn clear_queue(&mut self) {
+    self.event_queue.clear();
+  }
+
+  pub fn queue_len(&self) -> usize {
+    self.event_queue.len()
+  }
+
+  // Asks a copy of the dispatcher, so a stateful dispatcher isn't advanced.
+  pub fn peek_next(&self) -> Option<&Event<Channel, Payload>> {
+    self.event_queue.get(self.next_index()?)
+  }
+
+  pub fn peek_next_mut(&mut self) -> Option<&mut Payload> {
+    let index = self.next_index()?;
+    self.event_queue.get_mut(index).map(|event| &mut event.payload)
+  }
+
+  pub fn last_error(&self) -> Option<&BusError<Channel>> {
+    self.last_error.as_ref()
+  }
+
+  pub fn listener_error(&self) -> Option<&E> {
+    self.listener_error.as_ref()
+  }
+
+  pub fn metrics(&self) -> &Metrics<Channel> {
+    &self.metrics
+  }
+
+  pub fn reset_metrics(&mut self) {
+    self.metrics = Metrics::new();
+  }
+
+  pub fn timing(&self) -> &TimingStats<Channel> {
+    &self.timing
+  }
+
+  pub fn history(&self) -> &VecDeque<ProcessedEvent<Channel, Payload>> {
+    &self.history
+  }
+
+  pub fn history_for<'b>(&'b self, channel: &'b Channel) -> impl Iterator<Item = &'b Event<Channel, Payload>> + use<'b, 'a, Context, Channel, Payload, E> {
+    let channel = self.normalize(channel.clone());
+    self.history.iter().map(|processed| &processed.event).filter(move |event| event.channel == channel)
+  }
+
+  pub fn clear_history(&mut self) {
+    self.history.clear();
+  }
+
+  pub fn take_queue(&mut self) -> Vec<Event<Channel, Payload>> {
+    self.event_queue.drain().map(|(event, _)| event).collect()
+  }
+
+  pub fn pause(&mut self) {
+    self.paused = true;
+  }
+
+  pub fn resume(&mut self) {
+    self.paused = false;
+    self.drain();
+  }
+
+  pub fn enqueue(&mut self, event: Event<Channel, Payload>) {
+    self.push_event(event, Trace::root());
+  }
+
+  fn push_event(&mut self, event: Event<Channel, Payload>, trace: Trace<Channel, Payload>) {
+    let event = Event { channel: self.normalize(event.channel), payload: event.payload };
+    if self.is_pending(&event) {
+      return;
+    }
+    if self.event_queue.len() >= self.max_queue {
+      if self.overflow.is_none() {
+        self.overflow = Some(QueueOverflow(event));
+      }
+      return;
+    }
+    self.event_queue.push(event, trace);
+  }
+
+  fn is_pending(&self, event: &Event<Channel, Payload>) -> bool {
+    self.coalesce.is_some_and(|same| self.event_queue.iter().any(|pending| same(pending, event)))
+  }
+
+  pub fn step(&mut self) -> bool {
+    self.run(1, &mut |_| {});
+    !self.event_queue.is_empty()
+  }
+
+  pub fn process_budget(&mut self, max_events: usize) -> usize {
+    let start = self.seq;
+    self.run(max_events, &mut |_| {});
+    (self.seq - start) as usize
+  }
+
+  pub fn drain_sorted(&mut self) where Channel: Ord {
+    let mut pending: Vec<_> = self.event_queue.drain().collect();
+    pending.sort_by(|a, b| a.0.channel.cmp(&b.0.channel));
+    for (event, trace) in pending {
+      self.event_queue.push(event, trace);
+    }
+    self.drain();
+  }
+
+  pub fn drain_with<F: FnMut(&Event<Channel, Payload>)>(&mut self, mut observer: F) {
+    self.run(usize::MAX, &mut observer);
+  }
+
+  fn drain(&mut self) -> usize {
+    self.run(usize::MAX, &mut |_| {})
+  }
+
+  fn run(&mut self, budget: usize, observer: &mut dyn FnMut(&Event<Channel, Payload>)) -> usize {
+    let mut invoked = 0;
+    if let Err(error) = self.process_queue(&mut invoked, budget, observer) {
+      self.listener_error = Some(error);
+    }
+    invoked
+  }
+
+  fn add_subscription(&mut self, channel: Channel, mut subscription: Subscription<'a, Context, Channel, Payload, E>) -> SubscriptionId {
+    let id = SubscriptionId(self.next_id);
+    self.next_id += 1;
+    subscription.id = id;
+    let channel = self.normalize(channel);
+    self.track_channel(&channel);
+    insert_by_priority(self.listeners.entry(channel).or_default(), subscription);
+    id
+  }
+
+  fn add_pattern_subscription(&mut self, key: Channel, matches: Matcher<'a, Channel>, listener: Listener<Context, Channel, Payload>) -> SubscriptionId {
+    let id = SubscriptionId(self.next_id);
+    self.next_id += 1;
+    let mut subscription = Subscription::from_listener(listener);
+    subscription.id = id;
+    self.pattern_listeners.push(PatternSubscription { key, matches, subscription });
+    id
+  }
+
+  // Forgets the sinks of forwards whose subscription is gone.
+  fn drop_forwarders(&mut self) {
+    let listeners = &self.listeners;
+    self.forwarders.retain(|id, _| listeners.values().any(|existing_vec| existing_vec.iter().any(|s| s.id == *id)));
+  }
+
+  fn normalize(&self, channel: Channel) -> Channel {
+    normalized(self.normalizer, channel)
+  }
+
+  fn track_channel(&mut self, channel: &Channel) {
+    if !self.listeners.contains_key(channel) {
+      self.channel_order.retain(|existing| existing != channel);
+      self.channel_order.push(channel.clone());
+    }
+  }
+}
+
+impl<'a, Context, Channel: Hash + Eq + Clone + 'a, E: 'a> Pubsub<'a, Context, Channel, AnyPayload, E> {
+  pub fn publish_typed<T: 'static + Clone>(&mut self, channel: Channel, value: T) -> usize {
+    self.publish(Event { channel, payload: Rc::new(value) })
+  }
+
+  pub fn subscribe_typed<T: 'static + Clone>(&mut self, channel: Channel, f: TypedListener<Context, Channel, T>) {
+    let mut subscription = Subscription::new(callback(move |context, _call, payload: Rc<AnyPayload>| {
+      Ok(payload.downcast_ref::<T>().map(|value| f(context, value.clone())).unwrap_or_default())
+    }), Some(f as usize));
+    subscription.filter = Some(|payload| payload.is::<T>());
+    self.add_subscription(channel, subscription);
+  }
+}
+
+impl<'a, Context, Channel: Hash + Eq + Clone + 'a, Payload: Clone + 'a, E: 'a> Extend<(Channel, Listener<Context, Channel, Payload>)> for Pubsub<'a, Context, Channel, Payload, E> {
+  fn extend<I: IntoIterator<Item = (Channel, Listener<Context, Channel, Payload>)>>(&mut self, pairs: I) {
+    for (channel, listener) in pairs {
+      self.subscribe(channel, listener);
+    }
+  }
+}
+
+impl<'a, Context, Channel: Hash + Eq + Clone + fmt::Debug + 'a, Payload: Clone + 'a, E: 'a> fmt::Debug for Pubsub<'a, Context, Channel, Payload, E> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let listeners: HashMap<&Channel, usize> = self.listeners.iter().map(|(channel, v)| (channel, live_count(v))).filter(|&(_, count)| count > 0).collect();
+    f.debug_struct("Pubsub")
+      .field("listeners", &listeners)
+      .field("global_listeners", &self.global_listeners.len())
+      .field("event_queue", &self.event_queue.len())
+      .finish()
+  }
+}
+
+#[test]
+fn no_listeners_should_not_change() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 0)
+}
+
+#[test]
+fn noop_listener_should_not_change() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn noop_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), noop_listener);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 0)
+}
+
+#[test]
+fn listener_on_same_channel_should_change() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn listener_on_different_channel_should_not_change() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("diff channel".to_string(), listener);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 0)
+}
+
+#[test]
+fn listener_can_trigger_more_events() {
+  struct TestContext {
+    data: isize
+  }
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener_with_triggers(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event {
+        channel: "test channel 2".to_string(),
+        payload: "payload".to_string()
+    }]
+  }
+
+  fn plain_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener_with_triggers);
+  pubsub.subscribe("test channel 2".to_string(), plain_listener);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 2)
+}
+
+#[test]
+fn unsubscribed_listener_should_not_change() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn increment(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  fn decrement(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data -= 10;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), increment);
+  pubsub.subscribe("test channel".to_string(), decrement);
+  assert!(pubsub.unsubscribe(&"test channel".to_string(), decrement));
+  assert!(!pubsub.unsubscribe(&"test channel".to_string(), decrement));
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn unsubscribing_last_listener_should_remove_channel() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  assert!(pubsub.unsubscribe(&"test channel".to_string(), listener));
+  assert!(!pubsub.listeners.contains_key("test channel"));
+  assert!(!pubsub.unsubscribe(&"diff channel".to_string(), listener));
+}
+
+#[test]
+fn unsubscribe_all_should_clear_channel() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.subscribe("test channel".to_string(), listener);
+  assert!(pubsub.unsubscribe_all(&"test channel".to_string()) == 2);
+  assert!(pubsub.unsubscribe_all(&"test channel".to_string()) == 0);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 0)
+}
+
+#[test]
+fn triggered_events_should_process_in_enqueue_order() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener_with_triggers(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "first".to_string(), payload: "payload".to_string() },
+      Event { channel: "second".to_string(), payload: "payload".to_string() }
+    ]
+  }
+
+  fn first_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("first".to_string());
+    Vec::new()
+  }
+
+  fn second_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("second".to_string());
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener_with_triggers);
+  pubsub.subscribe("first".to_string(), first_listener);
+  pubsub.subscribe("second".to_string(), second_listener);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.seen == vec!["first".to_string(), "second".to_string()])
+}
+
+#[test]
+fn closure_listener_should_capture_environment() {
+  use std::rc::Rc;
+  use std::cell::Cell;
+
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  let counter = Rc::new(Cell::new(0));
+  let captured = counter.clone();
+  pubsub.subscribe_fn("test channel".to_string(), Box::new(move |context: &mut TestContext, _msg: String| {
+    context.data += 1;
+    captured.set(captured.get() + 1);
+    Vec::new()
+  }));
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 1);
+  assert!(counter.get() == 1)
+}
+
+#[test]
+fn self_republishing_listener_should_stop_at_max_depth() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 5);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn republishing_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event {
+      channel: "test channel".to_string(),
+      payload: msg
+    }]
+  }
+
+  pubsub.subscribe("test channel".to_string(), republishing_listener);
+  pubsub.publish(event.clone());
+  assert!(pubsub.context.data == 5);
+
+  pubsub.publish(event);
+  assert!(pubsub.context.data == 10)
+}
+
+#[test]
+fn publish_should_count_invoked_listeners() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener_with_triggers(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event {
+      channel: "test channel 2".to_string(),
+      payload: "payload".to_string()
+    }]
+  }
+
+  fn plain_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  assert!(pubsub.publish(event.clone()) == 0);
+
+  pubsub.subscribe("test channel 2".to_string(), plain_listener);
+  assert!(pubsub.publish(Event { channel: "test channel 2".to_string(), payload: "payload".to_string() }) == 1);
+
+  pubsub.subscribe("test channel".to_string(), listener_with_triggers);
+  assert!(pubsub.publish(event) == 2);
+  assert!(pubsub.context.data == 3)
+}
+
+#[test]
+fn long_cascade_should_not_overflow() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, isize> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: 1,
+    channel: "count".to_string()
+  };
+
+  fn counting_listener(context: &mut TestContext, count: isize) -> Vec<Event<String, isize>> {
+    context.data = count;
+    if count < 10000 {
+      vec![Event { channel: "count".to_string(), payload: count + 1 }]
+    } else {
+      Vec::new()
+    }
+  }
+
+  pubsub.subscribe("count".to_string(), counting_listener);
+  assert!(pubsub.publish(event) == 10000);
+  assert!(pubsub.context.data == 10000)
+}
+
+#[test]
+fn global_listener_should_receive_every_channel() {
+  struct TestContext {
+    global: Vec<String>,
+    specific: isize
+  }
+
+  let mut test_context = TestContext { global: Vec::new(), specific: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn global_listener(context: &mut TestContext, channel: String, _msg: String) -> Vec<Event<String, String>> {
+    context.global.push(channel);
+    Vec::new()
+  }
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.specific += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe_all(global_listener);
+  pubsub.subscribe("test channel".to_string(), listener);
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "payload".to_string() }) == 2);
+  assert!(pubsub.publish(Event { channel: "diff channel".to_string(), payload: "payload".to_string() }) == 1);
+
+  assert!(pubsub.context.global == vec!["test channel".to_string(), "diff channel".to_string()]);
+  assert!(pubsub.context.specific == 1)
+}
+
+#[test]
+fn publish_all_should_dispatch_batch_in_order() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("a".to_string(), listener);
+  pubsub.subscribe("b".to_string(), listener);
+  let invoked = pubsub.publish_all(vec![
+    Event { channel: "a".to_string(), payload: "one".to_string() },
+    Event { channel: "b".to_string(), payload: "two".to_string() },
+    Event { channel: "a".to_string(), payload: "three".to_string() }
+  ]);
+
+  assert!(invoked == 3);
+  assert!(pubsub.context.seen == vec!["one".to_string(), "two".to_string(), "three".to_string()])
+}
+
+#[test]
+fn listener_and_channel_counts_should_reflect_subscriptions() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  assert!(pubsub.channel_count() == 0);
+  pubsub.subscribe("a".to_string(), listener);
+  pubsub.subscribe("a".to_string(), listener);
+  pubsub.subscribe("b".to_string(), listener);
+
+  assert!(pubsub.listener_count(&"a".to_string()) == 2);
+  assert!(pubsub.listener_count(&"b".to_string()) == 1);
+  assert!(pubsub.listener_count(&"c".to_string()) == 0);
+  assert!(pubsub.channel_count() == 2)
+}
+
+#[test]
+fn clear_queue_should_drop_pending_events() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "one".to_string() });
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "two".to_string() });
+  assert!(pubsub.queue_len() == 2);
+
+  pubsub.clear_queue();
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "three".to_string() }) == 1);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn failing_listener_should_stop_cascade() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "first".to_string()
+  };
+
+  fn first_listener(context: &mut TestContext, _msg: String) -> Result<Vec<Event<String, String>>, String> {
+    context.seen.push("first".to_string());
+    Ok(vec![Event { channel: "second".to_string(), payload: "payload".to_string() }])
+  }
+
+  fn second_listener(context: &mut TestContext, _msg: String) -> Result<Vec<Event<String, String>>, String> {
+    context.seen.push("second".to_string());
+    Err("second failed".to_string())
+  }
+
+  fn third_listener(context: &mut TestContext, _msg: String) -> Result<Vec<Event<String, String>>, String> {
+    context.seen.push("third".to_string());
+    Ok(Vec::new())
+  }
+
+  pubsub.subscribe_try("first".to_string(), first_listener);
+  pubsub.subscribe_try("second".to_string(), second_listener);
+  pubsub.subscribe_try("second".to_string(), third_listener);
+
+  assert!(pubsub.publish_try(event) == Err("second failed".to_string()));
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.seen == vec!["first".to_string(), "second".to_string()])
+}
+
+#[test]
+fn once_listener_should_fire_once() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn republishing_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event {
+      channel: "test channel".to_string(),
+      payload: msg
+    }]
+  }
+
+  pubsub.subscribe_once("test channel".to_string(), republishing_listener);
+  assert!(pubsub.publish(event.clone()) == 1);
+  assert!(pubsub.publish(event) == 0);
+  assert!(pubsub.listener_count(&"test channel".to_string()) == 0);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn debug_should_show_channels_and_queue_length() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  pubsub.subscribe("a".to_string(), listener);
+  pubsub.subscribe("b".to_string(), listener);
+  pubsub.enqueue(Event { channel: "a".to_string(), payload: "payload".to_string() });
+
+  let output = format!("{:?}", pubsub);
+  assert!(output.contains("\"a\": 1"));
+  assert!(output.contains("\"b\": 1"));
+  assert!(output.contains("event_queue: 1"))
+}
+
+#[test]
+fn events_should_compare_structurally() {
+  let event = Event { channel: "test channel".to_string(), payload: 1 };
+
+  assert_eq!(event, Event { channel: "test channel".to_string(), payload: 1 });
+  assert_ne!(event, Event { channel: "test channel".to_string(), payload: 2 });
+  assert_ne!(event, Event { channel: "diff channel".to_string(), payload: 1 });
+}
+
+#[test]
+fn routable_payloads_should_pick_their_channel() {
+  #[derive(Clone)]
+  enum Message {
+    Login(String),
+    Logout(String)
+  }
+
+  impl Routable<String> for Message {
+    fn channel(&self) -> String {
+      match *self {
+        Message::Login(_) => "login".to_string(),
+        Message::Logout(_) => "logout".to_string()
+      }
+    }
+  }
+
+  struct TestContext {
+    online: Vec<String>
+  }
+
+  let mut test_context = TestContext { online: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, Message> = Pubsub::new(&mut test_context);
+
+  fn login_listener(context: &mut TestContext, msg: Message) -> Vec<Event<String, Message>> {
+    if let Message::Login(user) = msg {
+      context.online.push(user);
+    }
+    Vec::new()
+  }
+
+  fn logout_listener(context: &mut TestContext, msg: Message) -> Vec<Event<String, Message>> {
+    if let Message::Logout(user) = msg {
+      context.online.retain(|u| *u != user);
+    }
+    Vec::new()
+  }
+
+  pubsub.subscribe("login".to_string(), login_listener);
+  pubsub.subscribe("logout".to_string(), logout_listener);
+  pubsub.publish_routable(Message::Login("alice".to_string()));
+  pubsub.publish_routable(Message::Login("bob".to_string()));
+  pubsub.publish_routable(Message::Logout("alice".to_string()));
+
+  assert!(pubsub.context.online == vec!["bob".to_string()])
+}
+
+#[test]
+fn iter_channels_should_yield_subscribed_channels() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  pubsub.subscribe("c".to_string(), listener);
+  pubsub.subscribe("a".to_string(), listener);
+  pubsub.subscribe("b".to_string(), listener);
+  pubsub.subscribe("a".to_string(), listener);
+
+  let mut channels: Vec<&String> = pubsub.iter_channels().collect();
+  channels.sort();
+  assert!(channels == vec!["a", "b", "c"])
+}
+
+#[test]
+fn unsubscribe_by_id_should_preserve_survivor_order() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn first(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("first".to_string());
+    Vec::new()
+  }
+
+  fn second(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("second".to_string());
+    Vec::new()
+  }
+
+  fn third(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("third".to_string());
+    Vec::new()
+  }
+
+  let first_id = pubsub.subscribe_with_id("test channel".to_string(), first);
+  let second_id = pubsub.subscribe_with_id("test channel".to_string(), second);
+  let third_id = pubsub.subscribe_with_id("test channel".to_string(), third);
+  assert!(first_id != second_id && second_id != third_id);
+
+  assert!(pubsub.unsubscribe_by_id(second_id));
+  assert!(!pubsub.unsubscribe_by_id(second_id));
+  pubsub.publish(event);
+
+  assert!(pubsub.context.seen == vec!["first".to_string(), "third".to_string()])
+}
+
+#[test]
+fn channel_aware_listener_should_see_originating_channel() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  #[allow(clippy::ptr_arg)]
+  fn listener(context: &mut TestContext, channel: &String, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(channel.clone());
+    Vec::new()
+  }
+
+  pubsub.subscribe_channel_aware("a".to_string(), listener);
+  pubsub.subscribe_channel_aware("b".to_string(), listener);
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "payload".to_string() });
+
+  assert!(pubsub.context.seen == vec!["a".to_string(), "b".to_string()])
+}
+
+#[test]
+fn higher_priority_listener_should_run_first() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn persist(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("persist".to_string());
+    Vec::new()
+  }
+
+  fn validate(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("validate".to_string());
+    Vec::new()
+  }
+
+  fn audit(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("audit".to_string());
+    Vec::new()
+  }
+
+  pubsub.subscribe_with_priority("test channel".to_string(), 1, persist);
+  pubsub.subscribe_with_priority("test channel".to_string(), 10, validate);
+  pubsub.subscribe_with_priority("test channel".to_string(), 1, audit);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.seen == vec!["validate".to_string(), "persist".to_string(), "audit".to_string()])
+}
+
+#[test]
+fn filtered_listener_should_skip_rejected_payloads() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, isize> = Pubsub::new(&mut test_context);
+
+  fn is_even(payload: &isize) -> bool {
+    payload % 2 == 0
+  }
+
+  fn listener(context: &mut TestContext, msg: isize) -> Vec<Event<String, isize>> {
+    context.data += msg;
+    Vec::new()
+  }
+
+  pubsub.subscribe_filtered("numbers".to_string(), is_even, listener);
+  let invoked: usize = (1..5).map(|n| pubsub.publish(Event { channel: "numbers".to_string(), payload: n })).sum();
+
+  assert!(invoked == 2);
+  assert!(pubsub.context.data == 6)
+}
+
+#[test]
+fn owned_pubsub_should_mutate_its_own_context() {
+  struct TestContext {
+    data: isize
+  }
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  fn build() -> PubsubOwned<TestContext, String, String> {
+    let mut pubsub = Pubsub::owned(TestContext { data: 0 });
+    pubsub.subscribe("test channel".to_string(), listener);
+    pubsub
+  }
+
+  let mut pubsub = build();
+  pubsub.publish(Event { channel: "test channel".to_string(), payload: "payload".to_string() });
+  assert!(pubsub.context().data == 1);
+
+  pubsub.context_mut().data = 10;
+  pubsub.publish(Event { channel: "test channel".to_string(), payload: "payload".to_string() });
+  assert!(pubsub.context().data == 11)
+}
+
+#[test]
+fn history_should_keep_most_recent_events() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_history(&mut test_context, 2);
+
+  pubsub.publish(Event { channel: "a".to_string(), payload: "one".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "two".to_string() });
+  pubsub.publish(Event { channel: "a".to_string(), payload: "three".to_string() });
+
+  let payloads: Vec<&String> = pubsub.history().iter().map(|p| &p.event.payload).collect();
+  assert!(payloads == vec!["two", "three"]);
+
+  pubsub.clear_history();
+  assert!(pubsub.history().is_empty())
+}
+
+#[test]
+fn replay_should_redispatch_history_to_new_listeners() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_history(&mut test_context, 10);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.publish(Event { channel: "a".to_string(), payload: "one".to_string() });
+  pubsub.publish(Event { channel: "a".to_string(), payload: "two".to_string() });
+  pubsub.subscribe("a".to_string(), listener);
+
+  assert!(pubsub.replay() == 2);
+  assert!(pubsub.history().len() == 2);
+  assert!(pubsub.context.seen == vec!["one".to_string(), "two".to_string()])
+}
+
+#[test]
+fn subscribe_unique_should_not_duplicate() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  assert!(pubsub.subscribe_unique("test channel".to_string(), listener));
+  assert!(!pubsub.subscribe_unique("test channel".to_string(), listener));
+  pubsub.publish(event.clone());
+  assert!(pubsub.context.data == 1);
+
+  pubsub.publish(event);
+  assert!(pubsub.context.data == 2)
+}
+
+#[test]
+fn publish_collect_should_return_cascade_in_dispatch_order() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener_with_triggers(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![
+      Event { channel: "first".to_string(), payload: "one".to_string() },
+      Event { channel: "second".to_string(), payload: "two".to_string() }
+    ]
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener_with_triggers);
+  let collected: Vec<Event<String, String>> = pubsub.publish_collect(event.clone()).into_iter().map(|p| p.event).collect();
+
+  assert!(collected == vec![
+    event,
+    Event { channel: "first".to_string(), payload: "one".to_string() },
+    Event { channel: "second".to_string(), payload: "two".to_string() }
+  ]);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn requested_unsubscribe_should_apply_after_cascade() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "a".to_string()
+  };
+
+  fn b_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("b".to_string());
+    Vec::new()
+  }
+
+  let handle = pubsub.handle();
+  pubsub.subscribe_fn("a".to_string(), Box::new(move |context: &mut TestContext, _msg: String| {
+    context.seen.push("a".to_string());
+    handle.request_unsubscribe_all("b".to_string());
+    vec![Event { channel: "b".to_string(), payload: "payload".to_string() }]
+  }));
+  pubsub.subscribe("b".to_string(), b_listener);
+
+  assert!(pubsub.publish(event) == 2);
+  assert!(pubsub.listener_count(&"b".to_string()) == 0);
+  assert!(pubsub.listener_count(&"a".to_string()) == 1);
+  assert!(pubsub.context.seen == vec!["a".to_string(), "b".to_string()])
+}
+
+#[test]
+fn peek_next_should_return_front_without_removing() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  assert!(pubsub.peek_next().is_none());
+
+  pubsub.enqueue(Event { channel: "a".to_string(), payload: "one".to_string() });
+  pubsub.enqueue(Event { channel: "b".to_string(), payload: "two".to_string() });
+
+  assert!(pubsub.peek_next() == Some(&Event { channel: "a".to_string(), payload: "one".to_string() }));
+  assert!(pubsub.queue_len() == 2)
+}
+
+#[test]
+fn peek_next_should_follow_dispatcher() {
+  #[derive(Clone)]
+  struct Lifo;
+
+  impl<Channel, Payload> Dispatcher<Channel, Payload> for Lifo {
+    fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize> {
+      queue.len().checked_sub(1)
+    }
+  }
+
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_dispatcher(&mut test_context, Lifo);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "first".to_string() });
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "second".to_string() });
+
+  assert!(pubsub.peek_next() == Some(&Event { channel: "test channel".to_string(), payload: "second".to_string() }));
+  assert!(pubsub.step());
+  assert!(pubsub.context.seen == vec!["second".to_string()])
+}
+
+#[test]
+fn metrics_should_count_dispatches_per_channel() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_metrics(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "b".to_string(), payload: "payload".to_string() }]
+  }
+
+  pubsub.subscribe("a".to_string(), listener);
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "payload".to_string() });
+
+  assert!(pubsub.metrics().total_events_processed == 5);
+  assert!(pubsub.metrics().channel_dispatches.get("a") == Some(&2));
+  assert!(pubsub.metrics().channel_dispatches.get("b") == Some(&3));
+
+  pubsub.reset_metrics();
+  assert!(pubsub.metrics().total_events_processed == 0);
+  assert!(pubsub.metrics().channel_dispatches.is_empty());
+  assert!(pubsub.context.data == 2)
+}
+
+#[test]
+fn metrics_should_stay_empty_when_disabled() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+
+  assert!(pubsub.metrics().total_events_processed == 0)
+}
+
+#[test]
+fn dead_letter_handler_should_see_unrouted_events() {
+  struct TestContext {
+    dead: Vec<(String, String)>
+  }
+
+  let mut test_context = TestContext { dead: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn dead_letter(context: &mut TestContext, channel: &String, payload: &String) {
+    context.dead.push((channel.clone(), payload.clone()));
+  }
+
+  pubsub.on_dead_letter(dead_letter);
+  pubsub.subscribe("known".to_string(), listener);
+  pubsub.publish(Event { channel: "known".to_string(), payload: "delivered".to_string() });
+  pubsub.publish(Event { channel: "typo".to_string(), payload: "lost".to_string() });
+
+  assert!(pubsub.context.dead == vec![("typo".to_string(), "lost".to_string())])
+}
+
+#[test]
+fn publish_checked_should_reject_unregistered_channels() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.register_channel("user.login".to_string());
+  pubsub.subscribe("user.login".to_string(), listener);
+
+  assert!(pubsub.publish_checked(Event { channel: "user.login".to_string(), payload: "payload".to_string() }) == Ok(1));
+  assert!(pubsub.publish_checked(Event { channel: "user.logn".to_string(), payload: "payload".to_string() }) == Err(UnknownChannel("user.logn".to_string())));
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn subscribe_many_should_register_on_every_channel() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe_many(vec!["a".to_string(), "b".to_string(), "c".to_string()], listener);
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "c".to_string(), payload: "payload".to_string() });
+
+  assert!(pubsub.context.data == 3)
+}
+
+#[test]
+fn simulate_should_not_touch_real_context() {
+  #[derive(Clone)]
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  let simulated = pubsub.simulate(event);
+
+  assert!(simulated.data == 1);
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.data == 0)
+}
+
+#[test]
+fn simulate_should_not_touch_real_bus() {
+  #[derive(Clone)]
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  let handle = pubsub.handle();
+  let forwarder = pubsub.forward("a".to_string());
+  pubsub.subscribe_fn("a".to_string(), Box::new(move |_context, _msg| {
+    handle.request_unsubscribe_all("b".to_string());
+    Vec::new()
+  }));
+  pubsub.subscribe("b".to_string(), listener);
+
+  let simulated = pubsub.simulate(Event { channel: "a".to_string(), payload: "dry run".to_string() });
+  assert!(simulated.data == 0);
+  assert!(forwarder.pending_len() == 0);
+
+  assert!(pubsub.publish(Event { channel: "b".to_string(), payload: "real".to_string() }) == 1);
+  assert!(pubsub.listener_count(&"b".to_string()) == 1);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn simulate_should_not_advance_real_dispatcher() {
+  #[derive(Clone)]
+  struct Alternating {
+    back: bool
+  }
+
+  impl<Channel, Payload> Dispatcher<Channel, Payload> for Alternating {
+    fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize> {
+      let index = if self.back { queue.len().checked_sub(1) } else if queue.is_empty() { None } else { Some(0) };
+      self.back = !self.back;
+      index
+    }
+  }
+
+  #[derive(Clone)]
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_dispatcher(&mut test_context, Alternating { back: false });
+
+  fn root_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "leaf".to_string(), payload: "a".to_string() },
+      Event { channel: "leaf".to_string(), payload: "b".to_string() }
+    ]
+  }
+
+  fn leaf_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe("leaf".to_string(), leaf_listener);
+  let event = Event { channel: "root".to_string(), payload: "go".to_string() };
+
+  assert!(pubsub.simulate(event.clone()).seen == vec!["b".to_string(), "a".to_string()]);
+  pubsub.publish(event);
+  assert!(pubsub.context.seen == vec!["b".to_string(), "a".to_string()])
+}
+
+#[test]
+fn from_listeners_should_install_prebuilt_map() {
+  struct TestContext {
+    data: isize
+  }
+
+  fn increment(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  fn add_ten(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 10;
+    Vec::new()
+  }
+
+  let mut map: HashMap<String, Vec<Listener<TestContext, String, String>>> = HashMap::new();
+  map.insert("a".to_string(), vec![increment, add_ten]);
+  map.insert("b".to_string(), vec![increment]);
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::from_listeners(&mut test_context, map);
+
+  assert!(pubsub.listener_count(&"a".to_string()) == 2);
+  assert!(pubsub.listener_count(&"b".to_string()) == 1);
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "payload".to_string() });
+
+  assert!(pubsub.context.data == 12)
+}
+
+#[test]
+fn rc_listeners_should_share_payload_without_cloning() {
+  use std::rc::Rc;
+
+  struct Heavy {
+    size: usize
+  }
+
+  impl Clone for Heavy {
+    fn clone(&self) -> Heavy {
+      panic!("payload should not be deep cloned")
+    }
+  }
+
+  struct TestContext {
+    data: usize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, Heavy> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, payload: Rc<Heavy>) -> Vec<Event<String, Heavy>> {
+    context.data += payload.size;
+    Vec::new()
+  }
+
+  pubsub.subscribe_rc("heavy".to_string(), listener);
+  pubsub.subscribe_rc("heavy".to_string(), listener);
+  assert!(pubsub.publish(Event { channel: "heavy".to_string(), payload: Heavy { size: 1024 } }) == 2);
+
+  assert!(pubsub.context.data == 2048)
+}
+
+#[test]
+fn step_should_process_one_event_at_a_time() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "one".to_string() });
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "two".to_string() });
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "three".to_string() });
+  assert!(pubsub.context.seen.is_empty());
+
+  assert!(pubsub.step());
+  assert!(pubsub.context.seen == vec!["one".to_string()]);
+  assert!(pubsub.step());
+  assert!(!pubsub.step());
+  assert!(!pubsub.step());
+
+  assert!(pubsub.context.seen == vec!["one".to_string(), "two".to_string(), "three".to_string()])
+}
+
+#[test]
+fn listener_hooks_should_wrap_each_listener() {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  static BEFORE: AtomicUsize = AtomicUsize::new(0);
+  static AFTER: AtomicUsize = AtomicUsize::new(0);
+
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  #[allow(clippy::ptr_arg)]
+  fn before(_channel: &String, _payload: &String) {
+    assert!(BEFORE.load(Ordering::SeqCst) == AFTER.load(Ordering::SeqCst));
+    BEFORE.fetch_add(1, Ordering::SeqCst);
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn after(_channel: &String, _payload: &String) {
+    AFTER.fetch_add(1, Ordering::SeqCst);
+  }
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    assert!(BEFORE.load(Ordering::SeqCst) == AFTER.load(Ordering::SeqCst) + 1);
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.set_listener_hook(before, after);
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.publish(Event { channel: "test channel".to_string(), payload: "payload".to_string() });
+
+  assert!(BEFORE.load(Ordering::SeqCst) == 2);
+  assert!(AFTER.load(Ordering::SeqCst) == 2);
+  assert!(pubsub.context.data == 2)
+}
+
+#[test]
+fn unsubscribe_matching_should_remove_tagged_subscriptions() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn foo_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("foo".to_string());
+    Vec::new()
+  }
+
+  fn bar_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("bar".to_string());
+    Vec::new()
+  }
+
+  fn plugin_foo(tag: &str) -> bool {
+    tag.starts_with("plugin:foo")
+  }
+
+  pubsub.subscribe_tagged("a".to_string(), "plugin:foo/a", foo_listener);
+  pubsub.subscribe_tagged("b".to_string(), "plugin:foo/b", foo_listener);
+  pubsub.subscribe_tagged("a".to_string(), "plugin:bar/a", bar_listener);
+  pubsub.subscribe_tagged("b".to_string(), "plugin:bar/b", bar_listener);
+
+  assert!(pubsub.unsubscribe_matching(plugin_foo) == 2);
+  assert!(pubsub.unsubscribe_matching(plugin_foo) == 0);
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "payload".to_string() });
+
+  assert!(pubsub.context.seen == vec!["bar".to_string(), "bar".to_string()])
+}
+
+#[test]
+fn cascade_should_process_breadth_first() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "root".to_string(),
+    channel: "root".to_string()
+  };
+
+  fn root_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "level 1".to_string(), payload: "a".to_string() },
+      Event { channel: "level 1".to_string(), payload: "b".to_string() }
+    ]
+  }
+
+  fn level_one_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("1{}", msg));
+    vec![Event { channel: "level 2".to_string(), payload: msg }]
+  }
+
+  fn level_two_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("2{}", msg));
+    Vec::new()
+  }
+
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe("level 1".to_string(), level_one_listener);
+  pubsub.subscribe("level 2".to_string(), level_two_listener);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.seen == vec!["1a".to_string(), "1b".to_string(), "2a".to_string(), "2b".to_string()])
+}
+
+#[test]
+fn contains_listener_should_report_subscribed_fns() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  let channel = "channel".to_string();
+  assert!(!pubsub.contains_listener(&channel, listener));
+  pubsub.subscribe(channel.clone(), listener);
+  assert!(pubsub.contains_listener(&channel, listener))
+}
+
+#[test]
+fn pump_should_publish_each_event_from_source() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let events = vec![
+    Event { channel: "a".to_string(), payload: "first".to_string() },
+    Event { channel: "b".to_string(), payload: "second".to_string() },
+    Event { channel: "a".to_string(), payload: "third".to_string() }
+  ];
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("a".to_string(), listener);
+  pubsub.subscribe("b".to_string(), listener);
+  pubsub.subscribe("b".to_string(), listener);
+
+  assert!(pubsub.pump(events) == 4);
+  assert!(pubsub.context.data == 4)
+}
+
+#[test]
+fn restore_subscriptions_should_drop_later_listeners() {
+  struct TestContext {
+    base_count: u32,
+    extra_count: u32
+  }
+
+  let mut test_context = TestContext { base_count: 0, extra_count: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn base_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.base_count += 1;
+    Vec::new()
+  }
+
+  fn extra_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.extra_count += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), base_listener);
+  let snap = pubsub.snapshot_subscriptions();
+  pubsub.subscribe("test channel".to_string(), extra_listener);
+  pubsub.subscribe("other channel".to_string(), extra_listener);
+  pubsub.restore_subscriptions(snap);
+  pubsub.publish(event);
+
+  assert!(pubsub.channel_count() == 1);
+  assert!(pubsub.context.base_count == 1);
+  assert!(pubsub.context.extra_count == 0)
+}
+
+#[test]
+fn publish_while_processing_should_only_enqueue() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  pubsub.handle.processing.set(true);
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "nested".to_string() }) == 0);
+  assert!(pubsub.queue_len() == 1);
+  assert!(pubsub.context.seen.is_empty());
+
+  pubsub.handle.processing.set(false);
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "outer".to_string() }) == 2);
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.seen == vec!["nested".to_string(), "outer".to_string()])
+}
+
+#[test]
+fn subscribe_opt_should_publish_returned_event() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "a".to_string()
+  };
+
+  fn a_listener(context: &mut TestContext, msg: String) -> Option<Event<String, String>> {
+    context.seen.push("a".to_string());
+    Some(Event { channel: "b".to_string(), payload: msg })
+  }
+
+  fn b_listener(context: &mut TestContext, _msg: String) -> Option<Event<String, String>> {
+    context.seen.push("b".to_string());
+    None
+  }
+
+  pubsub.subscribe_opt("a".to_string(), a_listener);
+  pubsub.subscribe_opt("b".to_string(), b_listener);
+
+  assert!(pubsub.publish(event) == 2);
+  assert!(pubsub.context.seen == vec!["a".to_string(), "b".to_string()])
+}
+
+#[test]
+fn max_queue_should_report_overflowing_event() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_queue(&mut test_context, 2);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "root".to_string()
+  };
+
+  fn root_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "leaf".to_string(), payload: "one".to_string() },
+      Event { channel: "leaf".to_string(), payload: "two".to_string() },
+      Event { channel: "leaf".to_string(), payload: "three".to_string() }
+    ]
+  }
+
+  fn leaf_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe("leaf".to_string(), leaf_listener);
+
+  let result = pubsub.publish_bounded(event);
+  assert!(result == Err(QueueOverflow(Event { channel: "leaf".to_string(), payload: "three".to_string() })));
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.seen == vec!["one".to_string(), "two".to_string()])
+}
+
+#[test]
+fn max_queue_should_bound_direct_enqueues() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_queue(&mut test_context, 1);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.pause();
+  for payload in ["one", "two", "three"] {
+    pubsub.enqueue(Event { channel: "test channel".to_string(), payload: payload.to_string() });
+  }
+  pubsub.publish_all(vec![Event { channel: "test channel".to_string(), payload: "four".to_string() }]);
+  assert!(pubsub.queue_len() == 1);
+
+  let result = pubsub.publish_bounded(Event { channel: "test channel".to_string(), payload: "five".to_string() });
+  assert!(result == Err(QueueOverflow(Event { channel: "test channel".to_string(), payload: "five".to_string() })));
+  pubsub.resume();
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn extend_should_subscribe_each_pair() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  let pairs: Vec<(String, Listener<TestContext, String, String>)> = vec![
+    ("a".to_string(), listener),
+    ("a".to_string(), listener),
+    ("b".to_string(), listener)
+  ];
+  pubsub.extend(pairs);
+
+  assert!(pubsub.listener_count(&"a".to_string()) == 2);
+  assert!(pubsub.listener_count(&"b".to_string()) == 1)
+}
+
+#[test]
+fn publish_until_should_detect_target_in_cascade() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "done".to_string(), payload: "finished".to_string() }]
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn is_done(channel: &String, _payload: &String) -> bool {
+    channel == "done"
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn is_missing(channel: &String, _payload: &String) -> bool {
+    channel == "missing"
+  }
+
+  pubsub.subscribe("start".to_string(), listener);
+
+  assert!(pubsub.publish_until(Event { channel: "start".to_string(), payload: "go".to_string() }, is_done));
+  assert!(!pubsub.publish_until(Event { channel: "start".to_string(), payload: "go".to_string() }, is_missing));
+  assert!(pubsub.context.data == 2)
+}
+
+#[test]
+fn retain_channels_should_drop_failing_channels() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn is_kept(channel: &String) -> bool {
+    !channel.starts_with("temp/")
+  }
+
+  pubsub.subscribe("temp/a".to_string(), listener);
+  pubsub.subscribe("temp/b".to_string(), listener);
+  pubsub.subscribe("keep".to_string(), listener);
+  pubsub.retain_channels(is_kept);
+
+  assert!(pubsub.publish(Event { channel: "temp/a".to_string(), payload: "a".to_string() }) == 0);
+  assert!(pubsub.publish(Event { channel: "temp/b".to_string(), payload: "b".to_string() }) == 0);
+  assert!(pubsub.publish(Event { channel: "keep".to_string(), payload: "keep".to_string() }) == 1);
+  assert!(pubsub.context.seen == vec!["keep".to_string()])
+}
+
+#[test]
+fn merge_should_combine_snapshot_listeners() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut first_context = TestContext { seen: Vec::new() };
+  let mut second_context = TestContext { seen: Vec::new() };
+  let mut first: Pubsub<TestContext, String, String> = Pubsub::new(&mut first_context);
+  let mut second: Pubsub<TestContext, String, String> = Pubsub::new(&mut second_context);
+
+  fn first_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("first".to_string());
+    Vec::new()
+  }
+
+  fn second_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("second".to_string());
+    Vec::new()
+  }
+
+  first.subscribe("shared".to_string(), first_listener);
+  second.subscribe("shared".to_string(), second_listener);
+  second.subscribe("other".to_string(), second_listener);
+
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  pubsub.merge(first.snapshot_subscriptions());
+  pubsub.merge(second.snapshot_subscriptions());
+
+  assert!(pubsub.publish(Event { channel: "shared".to_string(), payload: "payload".to_string() }) == 2);
+  assert!(pubsub.listener_count(&"other".to_string()) == 1);
+  assert!(pubsub.context.seen == vec!["first".to_string(), "second".to_string()]);
+  assert!(first.context.seen.is_empty() && second.context.seen.is_empty())
+}
+
+#[test]
+fn merge_should_assign_fresh_subscription_ids() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut other_context = TestContext { seen: Vec::new() };
+  let mut other: Pubsub<TestContext, String, String> = Pubsub::new(&mut other_context);
+
+  fn own_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("own".to_string());
+    Vec::new()
+  }
+
+  fn merged_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("merged".to_string());
+    Vec::new()
+  }
+
+  other.subscribe("b".to_string(), merged_listener);
+
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let own = pubsub.subscribe_with_id("a".to_string(), own_listener);
+  pubsub.merge(other.snapshot_subscriptions());
+
+  let ids: Vec<SubscriptionId> = pubsub.list_subscriptions().into_iter().map(|(_, id)| id).collect();
+  assert!(ids.len() == 2 && ids[0] == own && ids[1] != own);
+
+  assert!(pubsub.unsubscribe_by_id(ids[1]));
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "payload".to_string() });
+  assert!(pubsub.context.seen == vec!["own".to_string()])
+}
+
+#[test]
+fn strict_mode_should_reject_unrouted_emission() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_strict(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "nowhere".to_string(), payload: "lost".to_string() }]
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  let result = pubsub.publish_strict(event);
+  assert!(result == Err(UnroutedEvent(Event { channel: "nowhere".to_string(), payload: "lost".to_string() })));
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn strict_mode_should_reject_unrouted_root_event() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_strict(&mut test_context);
+  let event = Event {
+    payload: "lost".to_string(),
+    channel: "nowhere".to_string()
+  };
+
+  let result = pubsub.publish_strict(event.clone());
+  assert!(result == Err(UnroutedEvent(event)));
+  assert!(pubsub.last_error() == Some(&BusError::NoListeners("nowhere".to_string())))
+}
+
+#[test]
+fn subscribe_weak_should_drop_listener_with_owner() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  let owner = Rc::new(RefCell::new("plugin".to_string()));
+  pubsub.subscribe_weak("test channel".to_string(), Rc::downgrade(&owner), listener);
+
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "first".to_string() }) == 1);
+  drop(owner);
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "second".to_string() }) == 0);
+  assert!(pubsub.listener_count(&"test channel".to_string()) == 0);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn dead_weak_listeners_should_leave_event_unrouted() {
+  struct TestContext {
+    dead: Vec<String>
+  }
+
+  let mut test_context = TestContext { dead: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_strict(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn dead_letter(context: &mut TestContext, _channel: &String, payload: &String) {
+    context.dead.push(payload.clone());
+  }
+
+  let owner = Rc::new(RefCell::new("plugin".to_string()));
+  pubsub.on_dead_letter(dead_letter);
+  pubsub.subscribe_weak("test channel".to_string(), Rc::downgrade(&owner), listener);
+  drop(owner);
+
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "lost".to_string() }) == 0);
+  assert!(pubsub.last_error() == Some(&BusError::NoListeners("test channel".to_string())));
+  assert!(pubsub.context.dead == vec!["lost".to_string()])
+}
+
+#[test]
+fn dead_weak_listeners_should_not_route_or_count() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_strict(&mut test_context);
+
+  fn forwarding_listener(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "b".to_string(), payload: msg }]
+  }
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  let owner = Rc::new(RefCell::new("plugin".to_string()));
+  pubsub.subscribe("a".to_string(), forwarding_listener);
+  pubsub.subscribe_weak("b".to_string(), Rc::downgrade(&owner), listener);
+  drop(owner);
+
+  assert!(pubsub.listener_count(&"b".to_string()) == 0);
+  assert!(pubsub.manifest() == vec![("a".to_string(), 1)]);
+  assert!(pubsub.channel_count() == 1);
+  assert!(pubsub.iter_channels().collect::<Vec<_>>() == vec!["a"]);
+  assert!(pubsub.list_subscriptions().len() == 1);
+  assert!(format!("{:?}", pubsub).contains("listeners: {\"a\": 1}"));
+
+  let result = pubsub.publish_strict(Event { channel: "a".to_string(), payload: "lost".to_string() });
+  assert!(result == Err(UnroutedEvent(Event { channel: "b".to_string(), payload: "lost".to_string() })));
+  assert!(pubsub.unsubscribe_all(&"b".to_string()) == 0)
+}
+
+#[test]
+fn iter_channels_should_follow_subscription_order() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  fn wildcard(context: &mut TestContext, channel: String, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("*{}", channel));
+    Vec::new()
+  }
+
+  for channel in ["delta", "alpha", "charlie", "bravo"].iter() {
+    pubsub.subscribe(channel.to_string(), listener);
+  }
+  pubsub.subscribe_all(wildcard);
+  pubsub.unsubscribe_all(&"alpha".to_string());
+  pubsub.subscribe("alpha".to_string(), listener);
+
+  let channels: Vec<String> = pubsub.iter_channels().cloned().collect();
+  assert!(channels == vec!["delta".to_string(), "charlie".to_string(), "bravo".to_string(), "alpha".to_string()]);
+
+  for channel in channels {
+    pubsub.publish(Event { channel: channel.clone(), payload: channel });
+  }
+  assert!(pubsub.context.seen == vec![
+    "delta".to_string(), "*delta".to_string(),
+    "charlie".to_string(), "*charlie".to_string(),
+    "bravo".to_string(), "*bravo".to_string(),
+    "alpha".to_string(), "*alpha".to_string()
+  ])
+}
+
+#[test]
+fn publish_ref_should_leave_event_with_caller() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  assert!(pubsub.publish_ref(&event) == 1);
+  assert!(pubsub.publish(event) == 1);
+  assert!(pubsub.context.seen == vec!["test payload".to_string(), "test payload".to_string()])
+}
+
+#[test]
+fn listener_panic_should_not_stop_remaining_listeners() {
+  use std::cell::Cell;
+
+  thread_local! {
+    static PANICKED: Cell<u32> = const { Cell::new(0) };
+  }
+
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn panicking_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    panic!("misbehaving listener");
+  }
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn on_panic(_channel: &String) {
+    PANICKED.with(|count| count.set(count.get() + 1));
+  }
+
+  pubsub.on_listener_panic(on_panic);
+  pubsub.subscribe("test channel".to_string(), panicking_listener);
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  assert!(pubsub.publish(event) == 2);
+  assert!(PANICKED.with(|count| count.get()) == 1);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn unhandled_listener_panic_should_not_leave_bus_processing() {
+  use std::panic::{self, AssertUnwindSafe};
+
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn panicking_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    panic!("misbehaving listener");
+  }
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("panic channel".to_string(), panicking_listener);
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  let outcome = panic::catch_unwind(AssertUnwindSafe(|| pubsub.publish(Event { channel: "panic channel".to_string(), payload: "boom".to_string() })));
+  assert!(outcome.is_err());
+  assert!(!pubsub.is_processing());
+
+  assert!(pubsub.try_publish(Event { channel: "test channel".to_string(), payload: "after".to_string() }) == Ok(1));
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn history_for_should_filter_by_channel() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_history(&mut test_context, 10);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("errors".to_string(), listener);
+  pubsub.publish(Event { channel: "errors".to_string(), payload: "first".to_string() });
+  pubsub.publish(Event { channel: "info".to_string(), payload: "ignored".to_string() });
+  pubsub.publish(Event { channel: "errors".to_string(), payload: "second".to_string() });
+
+  let errors = "errors".to_string();
+  let payloads: Vec<&String> = pubsub.history_for(&errors).map(|event| &event.payload).collect();
+  assert!(payloads == vec!["first", "second"]);
+  assert!(pubsub.context.data == 2)
+}
+
+#[test]
+fn middleware_should_transform_payload_once_per_event() {
+  use std::cell::Cell;
+
+  thread_local! {
+    static TRANSFORMS: Cell<u32> = const { Cell::new(0) };
+  }
+
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn uppercase(_channel: &String, payload: String) -> String {
+    TRANSFORMS.with(|count| count.set(count.get() + 1));
+    payload.to_uppercase()
+  }
+
+  pubsub.add_middleware(uppercase);
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.publish(event);
+
+  assert!(TRANSFORMS.with(|count| count.get()) == 1);
+  assert!(pubsub.context.seen == vec!["TEST PAYLOAD".to_string(), "TEST PAYLOAD".to_string()])
+}
+
+#[test]
+fn pause_should_hold_events_until_resume() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.pause();
+  for payload in ["one", "two", "three"].iter() {
+    assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: payload.to_string() }) == 0);
+  }
+  assert!(pubsub.queue_len() == 3);
+  assert!(pubsub.context.seen.is_empty());
+
+  pubsub.resume();
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.seen == vec!["one".to_string(), "two".to_string(), "three".to_string()])
+}
+
+#[test]
+fn subscribe_consuming_should_stop_remaining_listeners() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn consuming_listener(context: &mut TestContext, _msg: String) -> (Vec<Event<String, String>>, bool) {
+    context.seen.push("a".to_string());
+    (vec![Event { channel: "follow up".to_string(), payload: "next".to_string() }], true)
+  }
+
+  fn skipped_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("b".to_string());
+    Vec::new()
+  }
+
+  fn follow_up_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe_consuming("test channel".to_string(), consuming_listener);
+  pubsub.subscribe("test channel".to_string(), skipped_listener);
+  pubsub.subscribe("follow up".to_string(), follow_up_listener);
+
+  assert!(pubsub.publish(event) == 2);
+  assert!(pubsub.context.seen == vec!["a".to_string(), "next".to_string()])
+}
+
+#[test]
+fn is_processing_should_be_true_only_during_dispatch() {
+  struct TestContext {
+    handle: Option<Handle<String>>,
+    observed: Vec<bool>
+  }
+
+  let mut test_context = TestContext { handle: None, observed: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "test channel".to_string()
+  };
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    let processing = context.handle.as_ref().is_some_and(|handle| handle.is_processing());
+    context.observed.push(processing);
+    Vec::new()
+  }
+
+  pubsub.context.handle = Some(pubsub.handle());
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  assert!(!pubsub.is_processing());
+  pubsub.publish(event);
+  assert!(!pubsub.is_processing());
+  assert!(pubsub.context.observed == vec![true])
+}
+
+#[test]
+fn subscriptions_with_tag_should_return_matching_ids() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  let first = pubsub.subscribe_tagged("a".to_string(), "plugin", listener);
+  pubsub.subscribe_tagged("a".to_string(), "core", listener);
+  let second = pubsub.subscribe_tagged("b".to_string(), "plugin", listener);
+
+  assert!(pubsub.subscriptions_with_tag("plugin") == vec![first, second]);
+  assert!(pubsub.subscriptions_with_tag("missing").is_empty())
+}
+
+#[test]
+fn processed_events_should_carry_contiguous_sequence_numbers() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_history(&mut test_context, 10);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "root".to_string()
+  };
+
+  fn root_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![
+      Event { channel: "other".to_string(), payload: "one".to_string() },
+      Event { channel: "leaf".to_string(), payload: "two".to_string() }
+    ]
+  }
+
+  fn leaf_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "other".to_string(), payload: msg }]
+  }
+
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe("leaf".to_string(), leaf_listener);
+  pubsub.publish(event.clone());
+  let collected = pubsub.publish_collect(event);
+
+  let history: Vec<u64> = pubsub.history().iter().map(|p| p.seq).collect();
+  assert!(history == (0..8).collect::<Vec<u64>>());
+  let seqs: Vec<u64> = collected.iter().map(|p| p.seq).collect();
+  assert!(seqs == vec![4, 5, 6, 7]);
+  assert!(pubsub.context.data == 4)
+}
+
+#[test]
+fn scoped_subscription_should_unsubscribe_on_drop() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  {
+    let mut scoped = pubsub.subscribe_scoped("test channel".to_string(), listener);
+    assert!(scoped.publish(Event { channel: "test channel".to_string(), payload: "inside".to_string() }) == 1);
+  }
+
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "outside".to_string() }) == 0);
+  assert!(pubsub.listener_count(&"test channel".to_string()) == 0);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn drain_with_should_observe_events_in_order() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  for payload in ["one", "two", "three"].iter() {
+    pubsub.enqueue(Event { channel: "test channel".to_string(), payload: payload.to_string() });
+  }
+
+  let mut observed = Vec::new();
+  pubsub.drain_with(|event| observed.push(event.payload.clone()));
+
+  assert!(observed == vec!["one".to_string(), "two".to_string(), "three".to_string()]);
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.data == 3)
+}
+
+#[test]
+fn drain_with_should_respect_pause_and_max_depth() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 3);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "test channel".to_string(), payload: msg }]
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "loop".to_string() });
+
+  let mut observed = 0;
+  pubsub.pause();
+  pubsub.drain_with(|_| observed += 1);
+  assert!(observed == 0 && pubsub.queue_len() == 1);
+
+  pubsub.paused = false;
+  pubsub.drain_with(|_| observed += 1);
+  assert!(observed == 3);
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.data == 3)
+}
+
+#[test]
+fn step_should_count_depth_across_calls() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 3);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "test channel".to_string(), payload: msg }]
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "loop".to_string() });
+
+  let mut steps = 0;
+  while pubsub.step() && steps < 10 {
+    steps += 1;
+  }
+
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.data == 3)
+}
+
+#[test]
+fn max_depth_should_not_drop_independent_events_across_budgeted_calls() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 3);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("tick".to_string(), listener);
+  pubsub.enqueue(Event { channel: "tick".to_string(), payload: "0".to_string() });
+  for tick in 1..6 {
+    pubsub.enqueue(Event { channel: "tick".to_string(), payload: tick.to_string() });
+    assert!(pubsub.process_budget(1) == 1);
+  }
+  assert!(pubsub.process_budget(1) == 1);
+
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.data == 6);
+  assert!(pubsub.publish_all((0..4).map(|tick| Event { channel: "tick".to_string(), payload: tick.to_string() }).collect()) == 4);
+  assert!(pubsub.context.data == 10)
+}
+
+#[test]
+fn subscribe_prefix_should_receive_matching_channels() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn exact_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("exact {}", msg));
+    Vec::new()
+  }
+
+  fn prefix_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("prefix {}", msg));
+    Vec::new()
+  }
+
+  pubsub.subscribe("user.login".to_string(), exact_listener);
+  pubsub.subscribe_prefix("user.".to_string(), prefix_listener);
+
+  assert!(pubsub.publish(Event { channel: "user.login".to_string(), payload: "alice".to_string() }) == 2);
+  assert!(pubsub.publish(Event { channel: "user.logout".to_string(), payload: "bob".to_string() }) == 1);
+  assert!(pubsub.publish(Event { channel: "admin.login".to_string(), payload: "carol".to_string() }) == 0);
+  assert!(pubsub.context.seen == vec!["exact alice".to_string(), "prefix alice".to_string(), "prefix bob".to_string()])
+}
+
+#[test]
+fn prefix_subscription_should_be_listed_and_removable_by_id() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  let exact = pubsub.subscribe_with_id("user.login".to_string(), listener);
+  let prefix = pubsub.subscribe_prefix("user.".to_string(), listener);
+  assert!(pubsub.list_subscriptions() == vec![("user.login".to_string(), exact), ("user.".to_string(), prefix)]);
+
+  assert!(pubsub.unsubscribe_by_id(prefix));
+  assert!(!pubsub.unsubscribe_by_id(prefix));
+  assert!(pubsub.list_subscriptions() == vec![("user.login".to_string(), exact)]);
+  assert!(pubsub.publish(Event { channel: "user.login".to_string(), payload: "alice".to_string() }) == 1);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn take_queue_should_return_pending_events() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "one".to_string() });
+  pubsub.enqueue(Event { channel: "other".to_string(), payload: "two".to_string() });
+
+  let pending = pubsub.take_queue();
+  assert!(pending == vec![
+    Event { channel: "test channel".to_string(), payload: "one".to_string() },
+    Event { channel: "other".to_string(), payload: "two".to_string() }
+  ]);
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.publish_all(pending) == 1);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn queue_aware_listener_should_drop_pending_duplicates() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn dedupe_listener(context: &mut TestContext, msg: String, queue: &mut EventQueue<String, String>) {
+    context.seen.push(format!("dedupe {}", msg));
+    queue.retain(|pending| !(pending.channel == "dedupe" && pending.payload == msg));
+  }
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe_queue_aware("dedupe".to_string(), dedupe_listener);
+  pubsub.subscribe("other".to_string(), listener);
+  pubsub.enqueue(Event { channel: "dedupe".to_string(), payload: "x".to_string() });
+  pubsub.enqueue(Event { channel: "other".to_string(), payload: "y".to_string() });
+
+  assert!(pubsub.publish(Event { channel: "dedupe".to_string(), payload: "x".to_string() }) == 2);
+  assert!(pubsub.context.seen == vec!["dedupe x".to_string(), "y".to_string()])
+}
+
+#[test]
+fn queue_aware_listener_panic_should_be_caught() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn panicking_listener(_context: &mut TestContext, _msg: String, _queue: &mut EventQueue<String, String>) {
+    panic!("misbehaving listener");
+  }
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  #[allow(clippy::ptr_arg)]
+  fn on_panic(_channel: &String) {}
+
+  pubsub.on_listener_panic(on_panic);
+  pubsub.subscribe_queue_aware("test channel".to_string(), panicking_listener);
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "first".to_string() }) == 2);
+  assert!(!pubsub.is_processing());
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "second".to_string() }) == 2);
+  assert!(pubsub.context.seen == vec!["first".to_string(), "second".to_string()])
+}
+
+#[test]
+fn queue_aware_listener_events_should_respect_max_queue_and_coalescing() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_coalescing(&mut test_context);
+  pubsub.max_queue = 2;
+
+  fn pushing_listener(_context: &mut TestContext, _msg: String, queue: &mut EventQueue<String, String>) {
+    for payload in ["a", "a", "b", "c"] {
+      queue.push_back(Event { channel: "leaf".to_string(), payload: payload.to_string() });
+    }
+  }
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe_queue_aware("root".to_string(), pushing_listener);
+  pubsub.subscribe("leaf".to_string(), listener);
+
+  assert!(pubsub.publish_bounded(Event { channel: "root".to_string(), payload: "go".to_string() }).is_err());
+  assert!(pubsub.context.seen == vec!["a".to_string(), "b".to_string()])
+}
+
+#[test]
+fn coalescing_should_skip_identical_pending_events() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_coalescing(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.pause();
+  for _ in 0..3 {
+    pubsub.publish(Event { channel: "test channel".to_string(), payload: "same".to_string() });
+  }
+  assert!(pubsub.queue_len() == 1);
+  pubsub.resume();
+  assert!(pubsub.context.data == 1);
+
+  pubsub.publish(Event { channel: "test channel".to_string(), payload: "same".to_string() });
+  assert!(pubsub.context.data == 2)
+}
+
+#[test]
+fn coalescing_should_apply_to_batch_publishes() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_coalescing(&mut test_context);
+  let event = Event { channel: "test channel".to_string(), payload: "same".to_string() };
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  fn deferring_listener(_context: &mut TestContext, msg: String) -> Emission<String, String> {
+    Emission {
+      now: Vec::new(),
+      deferred: vec![
+        Event { channel: "test channel".to_string(), payload: msg.clone() },
+        Event { channel: "test channel".to_string(), payload: msg }
+      ]
+    }
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.subscribe_deferring("defer".to_string(), deferring_listener);
+
+  assert!(pubsub.publish_all(vec![event.clone(), event.clone(), event.clone()]) == 1);
+  assert!(pubsub.publish_slice(&[event.clone(), event]) == 1);
+
+  pubsub.publish(Event { channel: "defer".to_string(), payload: "same".to_string() });
+  assert!(pubsub.flush_deferred() == 1);
+  assert!(pubsub.context.data == 3)
+}
+
+#[test]
+fn subscribe_range_should_receive_channels_in_range() {
+  struct TestContext {
+    seen: Vec<u32>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, u32, u32> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: u32) -> Vec<Event<u32, u32>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe_range(0..10, listener);
+
+  assert!(pubsub.publish(Event { channel: 5, payload: 50 }) == 1);
+  assert!(pubsub.publish(Event { channel: 15, payload: 150 }) == 0);
+  assert!(pubsub.publish(Event { channel: 10, payload: 100 }) == 0);
+  assert!(pubsub.context.seen == vec![50])
+}
+
+#[test]
+fn range_subscription_should_be_listed_and_removable_by_id() {
+  struct TestContext {
+    seen: Vec<u32>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, u32, u32> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: u32) -> Vec<Event<u32, u32>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  let id = pubsub.subscribe_range(100..200, listener);
+  assert!(pubsub.list_subscriptions() == vec![(100, id)]);
+  assert!(pubsub.publish(Event { channel: 150, payload: 1 }) == 1);
+
+  assert!(pubsub.unsubscribe_by_id(id));
+  assert!(pubsub.list_subscriptions().is_empty());
+  assert!(pubsub.publish(Event { channel: 150, payload: 2 }) == 0);
+  assert!(pubsub.context.seen == vec![1])
+}
+
+#[test]
+fn rename_channel_should_move_listeners() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn old_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("old".to_string());
+    Vec::new()
+  }
+
+  fn new_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("new".to_string());
+    Vec::new()
+  }
+
+  pubsub.subscribe("new".to_string(), new_listener);
+  pubsub.subscribe("old".to_string(), old_listener);
+
+  assert!(pubsub.rename_channel(&"old".to_string(), "new".to_string()));
+  assert!(!pubsub.rename_channel(&"old".to_string(), "new".to_string()));
+  assert!(pubsub.listener_count(&"old".to_string()) == 0);
+  assert!(pubsub.publish(Event { channel: "new".to_string(), payload: "payload".to_string() }) == 2);
+  assert!(pubsub.context.seen == vec!["new".to_string(), "old".to_string()])
+}
+
+#[test]
+fn rename_channel_should_keep_priority_order() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn low_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("low".to_string());
+    Vec::new()
+  }
+
+  fn high_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("high".to_string());
+    Vec::new()
+  }
+
+  pubsub.subscribe_with_priority("new".to_string(), 0, low_listener);
+  pubsub.subscribe_with_priority("old".to_string(), 10, high_listener);
+
+  assert!(pubsub.rename_channel(&"old".to_string(), "new".to_string()));
+  assert!(pubsub.publish(Event { channel: "new".to_string(), payload: "payload".to_string() }) == 2);
+  assert!(pubsub.context.seen == vec!["high".to_string(), "low".to_string()])
+}
+
+#[test]
+fn try_publish_should_refuse_during_dispatch() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  pubsub.handle.processing.set(true);
+  assert!(pubsub.try_publish(Event { channel: "test channel".to_string(), payload: "nested".to_string() }) == Err(BusBusy));
+  assert!(pubsub.queue_len() == 0);
+  pubsub.handle.processing.set(false);
+
+  assert!(pubsub.try_publish(Event { channel: "test channel".to_string(), payload: "outer".to_string() }) == Ok(1));
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn subscribe_min_level_should_skip_lower_levels() {
+  #[derive(Clone)]
+  struct LogLine {
+    level: u8,
+    text: String
+  }
+
+  impl Leveled for LogLine {
+    fn level(&self) -> u8 {
+      self.level
+    }
+  }
+
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, LogLine> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, line: LogLine) -> Vec<Event<String, LogLine>> {
+    context.seen.push(line.text);
+    Vec::new()
+  }
+
+  pubsub.subscribe_min_level("log".to_string(), 3, listener);
+
+  assert!(pubsub.publish(Event { channel: "log".to_string(), payload: LogLine { level: 1, text: "debug".to_string() } }) == 0);
+  assert!(pubsub.publish(Event { channel: "log".to_string(), payload: LogLine { level: 5, text: "error".to_string() } }) == 1);
+  assert!(pubsub.context.seen == vec!["error".to_string()])
+}
+
+#[test]
+fn list_subscriptions_should_return_every_channel_and_id() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  let first = pubsub.subscribe_with_id("a".to_string(), listener);
+  let second = pubsub.subscribe_with_id("b".to_string(), listener);
+  let third = pubsub.subscribe_with_id("a".to_string(), listener);
+
+  assert!(pubsub.list_subscriptions() == vec![
+    ("a".to_string(), first),
+    ("a".to_string(), third),
+    ("b".to_string(), second)
+  ])
+}
+
+#[test]
+fn clone_config_with_should_share_wiring_across_contexts() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut first_context = TestContext { data: 0 };
+  let mut second_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut first_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  let mut clone = pubsub.clone_config_with(&mut second_context);
+
+  assert!(clone.publish(Event { channel: "test channel".to_string(), payload: "one".to_string() }) == 1);
+  assert!(clone.publish(Event { channel: "test channel".to_string(), payload: "two".to_string() }) == 1);
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "three".to_string() }) == 1);
+  assert!(clone.context.data == 2);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn clone_config_with_should_not_share_dispatcher() {
+  #[derive(Clone)]
+  struct Alternating {
+    back: bool
+  }
+
+  impl<Channel, Payload> Dispatcher<Channel, Payload> for Alternating {
+    fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize> {
+      let index = if self.back { queue.len().checked_sub(1) } else if queue.is_empty() { None } else { Some(0) };
+      self.back = !self.back;
+      index
+    }
+  }
+
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut first_context = TestContext { seen: Vec::new() };
+  let mut second_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_dispatcher(&mut first_context, Alternating { back: false });
+
+  fn root_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "leaf".to_string(), payload: "a".to_string() },
+      Event { channel: "leaf".to_string(), payload: "b".to_string() }
+    ]
+  }
+
+  fn leaf_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe("leaf".to_string(), leaf_listener);
+  let mut clone = pubsub.clone_config_with(&mut second_context);
+  let event = Event { channel: "root".to_string(), payload: "go".to_string() };
+
+  clone.publish(event.clone());
+  pubsub.publish(event);
+  assert!(clone.context.seen == vec!["b".to_string(), "a".to_string()]);
+  assert!(pubsub.context.seen == vec!["b".to_string(), "a".to_string()])
+}
+
+#[test]
+fn queue_watermark_should_fire_once_when_crossed() {
+  thread_local! {
+    static HIGH_WATER: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+  }
+
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "fan out".to_string()
+  };
+
+  fn fan_out_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    (0..5).map(|i| Event { channel: "leaf".to_string(), payload: i.to_string() }).collect()
+  }
+
+  fn leaf_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  fn on_high(len: usize) {
+    HIGH_WATER.with(|lens| lens.borrow_mut().push(len));
+  }
+
+  pubsub.set_queue_watermark(3, on_high);
+  pubsub.subscribe("fan out".to_string(), fan_out_listener);
+  pubsub.subscribe("leaf".to_string(), leaf_listener);
+  pubsub.publish(event);
+
+  let seen = HIGH_WATER.with(|lens| lens.borrow().clone());
+  assert!(seen.len() == 1 && seen[0] >= 3);
+  assert!(pubsub.context.data == 5)
+}
+
+#[test]
+fn typed_listeners_should_only_receive_matching_types() {
+  struct TestContext {
+    numbers: Vec<i32>,
+    words: Vec<String>
+  }
+
+  let mut test_context = TestContext { numbers: Vec::new(), words: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, AnyPayload> = Pubsub::new(&mut test_context);
+
+  fn number_listener(context: &mut TestContext, value: i32) -> Vec<Event<String, AnyPayload>> {
+    context.numbers.push(value);
+    Vec::new()
+  }
+
+  fn word_listener(context: &mut TestContext, value: String) -> Vec<Event<String, AnyPayload>> {
+    context.words.push(value);
+    Vec::new()
+  }
+
+  pubsub.subscribe_typed("numbers".to_string(), number_listener);
+  pubsub.subscribe_typed("words".to_string(), word_listener);
+  pubsub.subscribe_typed("words".to_string(), number_listener);
+
+  assert!(pubsub.publish_typed("numbers".to_string(), 42) == 1);
+  assert!(pubsub.publish_typed("words".to_string(), "hello".to_string()) == 1);
+  assert!(pubsub.publish_typed("numbers".to_string(), "wrong type".to_string()) == 0);
+  assert!(pubsub.context.numbers == vec![42]);
+  assert!(pubsub.context.words == vec!["hello".to_string()])
+}
+
+#[test]
+fn deferred_events_should_wait_for_flush() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "start".to_string()
+  };
+
+  fn start_listener(context: &mut TestContext, _msg: String) -> Emission<String, String> {
+    context.seen.push("start".to_string());
+    Emission {
+      now: vec![Event { channel: "log".to_string(), payload: "now".to_string() }],
+      deferred: vec![Event { channel: "log".to_string(), payload: "later".to_string() }]
+    }
+  }
+
+  fn log_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe_deferring("start".to_string(), start_listener);
+  pubsub.subscribe("log".to_string(), log_listener);
+
+  assert!(pubsub.publish(event) == 2);
+  assert!(pubsub.deferred_len() == 1);
+  assert!(pubsub.context.seen == vec!["start".to_string(), "now".to_string()]);
+
+  assert!(pubsub.flush_deferred() == 1);
+  assert!(pubsub.deferred_len() == 0);
+  assert!(pubsub.context.seen == vec!["start".to_string(), "now".to_string(), "later".to_string()])
+}
+
+#[test]
+fn deferred_events_should_stay_with_the_dispatching_bus() {
+  #[derive(Clone)]
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut merged_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "start".to_string()
+  };
+
+  fn start_listener(context: &mut TestContext, msg: String) -> Emission<String, String> {
+    context.data += 1;
+    Emission { now: Vec::new(), deferred: vec![Event { channel: "log".to_string(), payload: msg }] }
+  }
+
+  pubsub.subscribe_deferring("start".to_string(), start_listener);
+  pubsub.publish(event.clone());
+  assert!(pubsub.deferred_len() == 1);
+
+  assert!(pubsub.simulate(event.clone()).data == 2);
+  assert!(pubsub.deferred_len() == 1);
+
+  let mut merged: Pubsub<TestContext, String, String> = Pubsub::new(&mut merged_context);
+  merged.merge(pubsub.snapshot_subscriptions());
+  merged.publish(event);
+  assert!(merged.deferred_len() == 1);
+  assert!(pubsub.deferred_len() == 1)
+}
+
+#[test]
+fn subscribe_count_should_return_channel_total() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  assert!(pubsub.subscribe_count("test channel".to_string(), listener) == 1);
+  assert!(pubsub.subscribe_count("test channel".to_string(), listener) == 2);
+  assert!(pubsub.subscribe_count("other channel".to_string(), listener) == 1)
+}
+
+#[test]
+fn process_budget_should_cap_events_per_call() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  for i in 0..5 {
+    pubsub.enqueue(Event { channel: "test channel".to_string(), payload: i.to_string() });
+  }
+
+  assert!(pubsub.process_budget(2) == 2);
+  assert!(pubsub.process_budget(2) == 2);
+  assert!(pubsub.process_budget(2) == 1);
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.data == 5)
+}
+
+#[test]
+fn depth_aware_listener_should_see_cascade_level() {
+  struct TestContext {
+    depths: Vec<usize>
+  }
+
+  let mut test_context = TestContext { depths: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "recurse".to_string()
+  };
+
+  fn listener(context: &mut TestContext, msg: String, depth: usize) -> Vec<Event<String, String>> {
+    context.depths.push(depth);
+    if depth < 3 {
+      vec![Event { channel: "recurse".to_string(), payload: msg }]
+    } else {
+      Vec::new()
+    }
+  }
+
+  pubsub.subscribe_depth_aware("recurse".to_string(), listener);
+
+  assert!(pubsub.publish(event) == 4);
+  assert!(pubsub.context.depths == vec![0, 1, 2, 3])
+}
+
+#[test]
+fn depth_aware_listener_should_see_depth_of_copied_bus() {
+  #[derive(Clone)]
+  struct TestContext {
+    depths: Vec<usize>
+  }
+
+  let mut test_context = TestContext { depths: Vec::new() };
+  let mut copy_context = TestContext { depths: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 10);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "recurse".to_string()
+  };
+
+  fn listener(context: &mut TestContext, msg: String, depth: usize) -> Vec<Event<String, String>> {
+    context.depths.push(depth);
+    if depth < 3 {
+      vec![Event { channel: "recurse".to_string(), payload: msg }]
+    } else {
+      Vec::new()
+    }
+  }
+
+  pubsub.subscribe_depth_aware("recurse".to_string(), listener);
+
+  assert!(pubsub.simulate(event.clone()).depths == vec![0, 1, 2, 3]);
+
+  let mut copy = pubsub.clone_config_with(&mut copy_context);
+  assert!(copy.publish(event) == 4);
+  assert!(copy.context.depths == vec![0, 1, 2, 3])
+}
+
+#[test]
+fn depth_aware_listener_should_see_cascade_level_after_queue_aware_removal() {
+  struct TestContext {
+    depths: Vec<usize>
+  }
+
+  let mut test_context = TestContext { depths: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn root_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "q".to_string(), payload: "x".to_string() },
+      Event { channel: "dup".to_string(), payload: "x".to_string() },
+      Event { channel: "d".to_string(), payload: "first".to_string() }
+    ]
+  }
+
+  fn dedupe_listener(_context: &mut TestContext, _msg: String, queue: &mut EventQueue<String, String>) {
+    queue.retain(|pending| pending.channel != "dup");
+  }
+
+  fn depth_listener(context: &mut TestContext, msg: String, depth: usize) -> Vec<Event<String, String>> {
+    context.depths.push(depth);
+    if msg == "first" {
+      vec![Event { channel: "d".to_string(), payload: "again".to_string() }]
+    } else {
+      Vec::new()
+    }
+  }
+
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe_queue_aware("q".to_string(), dedupe_listener);
+  pubsub.subscribe_depth_aware("d".to_string(), depth_listener);
+  pubsub.publish(Event { channel: "root".to_string(), payload: "go".to_string() });
+
+  assert!(pubsub.context.depths == vec![1, 2])
+}
+
+#[test]
+fn run_from_should_publish_until_sender_disconnects() {
+  use std::sync::mpsc;
+  use std::thread;
+
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+
+  let (tx, rx) = mpsc::channel();
+  let producer = thread::spawn(move || {
+    for payload in ["one", "two", "three"].iter() {
+      tx.send(Event { channel: "test channel".to_string(), payload: payload.to_string() }).unwrap();
+    }
+  });
+
+  assert!(pubsub.run_from(rx) == 3);
+  producer.join().unwrap();
+  assert!(pubsub.context.seen == vec!["one".to_string(), "two".to_string(), "three".to_string()])
+}
+
+#[test]
+fn last_error_should_record_strict_dead_letters() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_strict(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.publish(Event { channel: "test channel".to_string(), payload: "routed".to_string() });
+  assert!(pubsub.last_error().is_none());
+
+  pubsub.publish(Event { channel: "nowhere".to_string(), payload: "lost".to_string() });
+  assert!(pubsub.last_error() == Some(&BusError::NoListeners("nowhere".to_string())));
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn ordered_global_listeners_should_run_by_key() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn first(context: &mut TestContext, channel: String, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("1 {}", channel));
+    Vec::new()
+  }
+
+  fn second(context: &mut TestContext, channel: String, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("2 {}", channel));
+    Vec::new()
+  }
+
+  pubsub.subscribe_all_ordered(2, second);
+  pubsub.subscribe_all_ordered(1, first);
+  pubsub.publish(Event { channel: "a".to_string(), payload: "payload".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "payload".to_string() });
+
+  assert!(pubsub.context.seen == vec!["1 a".to_string(), "2 a".to_string(), "1 b".to_string(), "2 b".to_string()])
+}
+
+#[test]
+fn set_listeners_should_replace_channel_listeners() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn old_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("old".to_string());
+    Vec::new()
+  }
+
+  fn first_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("first".to_string());
+    Vec::new()
+  }
+
+  fn second_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("second".to_string());
+    Vec::new()
+  }
+
+  pubsub.set_listeners("test channel".to_string(), vec![old_listener]);
+  pubsub.set_listeners("test channel".to_string(), vec![first_listener, second_listener]);
+
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "payload".to_string() }) == 2);
+  assert!(pubsub.context.seen == vec!["first".to_string(), "second".to_string()])
+}
+
+#[test]
+fn cycle_error_should_report_channel_path() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 5);
+  let event = Event {
+    payload: "ball".to_string(),
+    channel: "a".to_string()
+  };
+
+  fn ping(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "b".to_string(), payload: msg }]
+  }
+
+  fn pong(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![Event { channel: "a".to_string(), payload: msg }]
+  }
+
+  pubsub.subscribe("a".to_string(), ping);
+  pubsub.subscribe("b".to_string(), pong);
+
+  let result = pubsub.publish_cycle_checked(event);
+  assert!(result == Err(CycleError { path: vec!["a".to_string(), "b".to_string(), "a".to_string()], repeats: true }));
+  assert!(pubsub.context.data == 5)
+}
+
+#[test]
+fn cycle_error_should_ignore_fan_out_within_depth() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 3);
+  let event = Event {
+    payload: "ball".to_string(),
+    channel: "root".to_string()
+  };
+
+  fn root_listener(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "a".to_string(), payload: msg.clone() },
+      Event { channel: "a".to_string(), payload: msg.clone() },
+      Event { channel: "a".to_string(), payload: msg }
+    ]
+  }
+
+  fn leaf_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe("a".to_string(), leaf_listener);
+
+  assert!(pubsub.publish_cycle_checked(event) == Ok(4));
+  assert!(pubsub.context.data == 3)
+}
+
+#[test]
+fn cycle_error_should_report_chain_cut_without_loop() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 2);
+
+  fn a_listener(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "b".to_string(), payload: msg }]
+  }
+
+  fn b_listener(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "c".to_string(), payload: msg }]
+  }
+
+  fn c_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  pubsub.subscribe("a".to_string(), a_listener);
+  pubsub.subscribe("b".to_string(), b_listener);
+  pubsub.subscribe("c".to_string(), c_listener);
+
+  let result = pubsub.publish_cycle_checked(Event { channel: "a".to_string(), payload: "ball".to_string() });
+  assert!(result == Err(CycleError { path: vec!["a".to_string(), "b".to_string(), "c".to_string()], repeats: false }))
+}
+
+#[test]
+fn cycle_error_should_follow_the_causing_chain() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 6);
+  let event = Event {
+    payload: "ball".to_string(),
+    channel: "root".to_string()
+  };
+
+  fn root_listener(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "a".to_string(), payload: msg.clone() }, Event { channel: "side".to_string(), payload: msg }]
+  }
+
+  fn ping(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "b".to_string(), payload: msg }]
+  }
+
+  fn pong(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "a".to_string(), payload: msg }]
+  }
+
+  fn side(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe("a".to_string(), ping);
+  pubsub.subscribe("b".to_string(), pong);
+  pubsub.subscribe("side".to_string(), side);
+
+  let result = pubsub.publish_cycle_checked(event);
+  assert!(result == Err(CycleError { path: vec!["a".to_string(), "b".to_string(), "a".to_string()], repeats: true }))
+}
+
+#[test]
+fn drain_sorted_should_process_by_channel_order() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  for channel in ["b", "a", "c"].iter() {
+    pubsub.subscribe(channel.to_string(), listener);
+    pubsub.enqueue(Event { channel: channel.to_string(), payload: channel.to_string() });
+  }
+  pubsub.drain_sorted();
+
+  assert!(pubsub.queue_len() == 0);
+  assert!(pubsub.context.seen == vec!["a".to_string(), "b".to_string(), "c".to_string()])
+}
+
+#[test]
+fn on_idle_should_fire_once_after_cascade_settles() {
+  struct TestContext {
+    data: isize,
+    idle_count: u32
+  }
+
+  let mut test_context = TestContext { data: 0, idle_count: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let event = Event {
+    payload: "test payload".to_string(),
+    channel: "root".to_string()
+  };
+
+  fn root_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    vec![
+      Event { channel: "leaf".to_string(), payload: "one".to_string() },
+      Event { channel: "leaf".to_string(), payload: "two".to_string() }
+    ]
+  }
+
+  fn leaf_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  fn on_idle(context: &mut TestContext) {
+    context.idle_count += 1;
+  }
+
+  pubsub.set_on_idle(on_idle);
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.subscribe("leaf".to_string(), leaf_listener);
+  pubsub.publish(event);
+
+  assert!(pubsub.context.data == 3);
+  assert!(pubsub.context.idle_count == 1)
+}
+
+#[test]
+fn on_idle_should_not_fire_when_nothing_was_dispatched() {
+  struct TestContext {
+    idle_count: u32
+  }
+
+  let mut test_context = TestContext { idle_count: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn root_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "leaf".to_string(), payload: "one".to_string() }]
+  }
+
+  fn on_idle(context: &mut TestContext) {
+    context.idle_count += 1;
+  }
+
+  pubsub.set_on_idle(on_idle);
+  pubsub.subscribe("root".to_string(), root_listener);
+  pubsub.resume();
+  assert!(pubsub.process_budget(0) == 0);
+  assert!(pubsub.context.idle_count == 0);
+
+  pubsub.enqueue(Event { channel: "root".to_string(), payload: "go".to_string() });
+  assert!(pubsub.step());
+  assert!(!pubsub.step());
+  pubsub.resume();
+  assert!(pubsub.context.idle_count == 1)
+}
+
+#[test]
+fn forwarder_should_republish_onto_another_bus() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut a_context = TestContext { seen: Vec::new() };
+  let mut b_context = TestContext { seen: Vec::new() };
+  let mut bus_a: Pubsub<TestContext, String, String> = Pubsub::new(&mut a_context);
+  let mut bus_b: Pubsub<TestContext, String, String> = Pubsub::new(&mut b_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  let forwarder = bus_a.forward("shared".to_string());
+  bus_a.subscribe("local".to_string(), listener);
+  bus_b.subscribe("shared".to_string(), listener);
+
+  bus_a.publish(Event { channel: "shared".to_string(), payload: "hello".to_string() });
+  bus_a.publish(Event { channel: "local".to_string(), payload: "stays".to_string() });
+  assert!(forwarder.pending_len() == 1);
+  assert!(bus_b.context.seen.is_empty());
+
+  assert!(forwarder.flush_into(&mut bus_b) == 1);
+  assert!(forwarder.pending_len() == 0);
+  assert!(bus_a.context.seen == vec!["stays".to_string()]);
+  assert!(bus_b.context.seen == vec!["hello".to_string()])
+}
+
+#[test]
+fn removed_forward_should_stop_forwarding_and_drop_its_sink() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  let by_id = pubsub.forward("a".to_string());
+  let by_channel = pubsub.forward("b".to_string());
+  assert!(pubsub.forwarders.len() == 2);
+
+  assert!(pubsub.unsubscribe_by_id(by_id.id()));
+  assert!(pubsub.unsubscribe_all(&"b".to_string()) == 1);
+  assert!(pubsub.forwarders.is_empty());
+
+  pubsub.publish(Event { channel: "a".to_string(), payload: "dropped".to_string() });
+  pubsub.publish(Event { channel: "b".to_string(), payload: "dropped".to_string() });
+  assert!(by_id.pending_len() == 0);
+  assert!(by_channel.pending_len() == 0)
+}
+
+#[test]
+fn forwarder_should_only_collect_from_its_own_bus() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut copy_context = TestContext;
+  let mut merged_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  let forwarder = pubsub.forward("shared".to_string());
+
+  let mut copy = pubsub.clone_config_with(&mut copy_context);
+  let mut merged: Pubsub<TestContext, String, String> = Pubsub::new(&mut merged_context);
+  merged.merge(pubsub.snapshot_subscriptions());
+
+  assert!(copy.publish(Event { channel: "shared".to_string(), payload: "copy".to_string() }) == 1);
+  assert!(merged.publish(Event { channel: "shared".to_string(), payload: "merged".to_string() }) == 1);
+  assert!(forwarder.pending_len() == 0);
+
+  assert!(pubsub.publish(Event { channel: "shared".to_string(), payload: "real".to_string() }) == 1);
+  assert!(forwarder.pending_len() == 1)
+}
+
+#[test]
+fn publish_command_should_reject_event_channels() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
+  }
+
+  pubsub.subscribe_with_role("user.created".to_string(), Role::Event, listener);
+  pubsub.subscribe_with_role("create user".to_string(), Role::Command, listener);
+
+  let result = pubsub.publish_command(Event { channel: "user.created".to_string(), payload: "alice".to_string() });
+  assert!(result == Err(RoleMismatch { channel: "user.created".to_string(), expected: Role::Command, declared: Role::Event }));
+  assert!(pubsub.publish_command(Event { channel: "create user".to_string(), payload: "alice".to_string() }) == Ok(1));
+  assert!(pubsub.publish_event(Event { channel: "user.created".to_string(), payload: "alice".to_string() }) == Ok(1));
+  assert!(pubsub.context.data == 2)
+}
+
+#[test]
+fn indexed_listeners_should_receive_their_position() {
+  thread_local! {
+    static SEEN: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+  }
+
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn first(_context: &mut TestContext, _msg: String, index: usize) -> Vec<Event<String, String>> {
+    SEEN.with(|seen| seen.borrow_mut().push(index));
+    Vec::new()
+  }
+
+  fn second(_context: &mut TestContext, _msg: String, index: usize) -> Vec<Event<String, String>> {
+    SEEN.with(|seen| seen.borrow_mut().push(index));
+    Vec::new()
+  }
+
+  fn third(_context: &mut TestContext, _msg: String, index: usize) -> Vec<Event<String, String>> {
+    SEEN.with(|seen| seen.borrow_mut().push(index));
+    Vec::new()
+  }
+
+  pubsub.subscribe_indexed("stage".to_string(), first);
+  pubsub.subscribe_indexed("stage".to_string(), second);
+  pubsub.subscribe_indexed("stage".to_string(), third);
+  pubsub.publish(Event { channel: "stage".to_string(), payload: "go".to_string() });
+
+  SEEN.with(|seen| assert!(*seen.borrow() == vec![0, 1, 2]))
+}
+
+#[test]
+fn indexed_listener_should_see_position_on_merged_bus() {
+  struct TestContext {
+    indices: Vec<usize>
+  }
+
+  let mut test_context = TestContext { indices: Vec::new() };
+  let mut other_context = TestContext { indices: Vec::new() };
+  let mut other: Pubsub<TestContext, String, String> = Pubsub::new(&mut other_context);
 
-use std::hash::Hash;
-use std::collections::hash_map::HashMap;
-use std::collections::hash_map::Entry::{Occupied, Vacant};
+  fn plain(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
 
-#[derive(Clone)]
-pub struct Event<Channel, Payload> {
-  pub channel: Channel,
-  pub payload: Payload
+  fn indexed(context: &mut TestContext, _msg: String, index: usize) -> Vec<Event<String, String>> {
+    context.indices.push(index);
+    Vec::new()
+  }
+
+  other.subscribe_indexed("stage".to_string(), indexed);
+
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  pubsub.subscribe("stage".to_string(), plain);
+  pubsub.merge(other.snapshot_subscriptions());
+  pubsub.publish(Event { channel: "stage".to_string(), payload: "go".to_string() });
+
+  assert!(pubsub.context.indices == vec![1])
 }
 
-pub struct Pubsub<'a, Context:'a, Channel: Hash + Eq + Clone, Payload: Clone> {
-  pub context: &'a mut Context,
-  listeners: HashMap<Channel, Vec<fn(context: &mut Context, payload: Payload) -> Vec<Event<Channel, Payload>>>>,
-  event_queue: Vec<Event<Channel, Payload>>
+#[test]
+fn manifest_should_list_channels_with_listener_counts() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  pubsub.subscribe("orders".to_string(), listener);
+  pubsub.subscribe("orders".to_string(), listener);
+  pubsub.subscribe("shipments".to_string(), listener);
+
+  assert!(pubsub.manifest() == vec![("orders".to_string(), 2), ("shipments".to_string(), 1)])
 }
 
-impl<'a, Context, Channel: Hash + Eq + Clone, Payload: Clone> Pubsub<'a, Context, Channel, Payload> {
-  pub fn new(context: &mut Context) -> Pubsub<Context, Channel, Payload> {
-    Pubsub {
-      context: context,
-      listeners: HashMap::new(),
-      event_queue: Vec::new()
-    }
+#[test]
+fn disabled_group_should_skip_its_listeners() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn first(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
+    Vec::new()
   }
 
-  pub fn publish(&mut self, event: Event<Channel, Payload>) {
-    self.event_queue.push(event.clone());
-    self.process_queue();
+  fn second(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 10;
+    Vec::new()
   }
 
-  pub fn subscribe(&mut self, channel: Channel, listener: fn(&mut Context, Payload) -> Vec<Event<Channel, Payload>>) {
-    if !(Pubsub::try_existing(self.listeners.get_mut(&channel), listener)) {
-      let mut v = Vec::new();
-      v.push(listener);
-      self.listeners.insert(channel, v);
-    }
+  pubsub.subscribe_in_group("feature".to_string(), GroupId(1), first);
+  pubsub.subscribe_in_group("feature".to_string(), GroupId(1), second);
+
+  pubsub.disable_group(GroupId(1));
+  assert!(pubsub.publish(Event { channel: "feature".to_string(), payload: "off".to_string() }) == 0);
+  assert!(pubsub.context.data == 0);
+
+  pubsub.enable_group(GroupId(1));
+  assert!(pubsub.publish(Event { channel: "feature".to_string(), payload: "on".to_string() }) == 2);
+  assert!(pubsub.context.data == 11)
+}
+
+#[test]
+fn publish_slice_should_replay_borrowed_events() {
+  struct TestContext {
+    seen: Vec<String>
   }
 
-  fn process_event(&mut self, event: Event<Channel, Payload>)  {
-    let listeners_entry = self.listeners.entry(event.channel);
-    let ref mut context = self.context;
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
 
-    match listeners_entry {
-      Occupied(mut listeners) => for listener in listeners.get_mut().iter() {
-        let head = self.event_queue.clone();
-        let tail = (*listener)(*context, event.payload.clone());
-        self.event_queue = head + &*tail;
-      },
-      Vacant(_) => ()
-    }
+  fn created(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("created {}", msg));
+    Vec::new()
   }
 
-  fn process_queue(&mut self) {
-    let event_opt = self.event_queue.pop();
-    match event_opt {
-      Some(event) => self.process_event(event),
-      None => ()
-    }
-    if self.event_queue.len() > 0 { self.process_queue(); }
+  fn deleted(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("deleted {}", msg));
+    Vec::new()
   }
 
-  fn try_existing(existing: Option<&mut Vec<fn(&mut Context, Payload) -> Vec<Event<Channel, Payload>>>>, listener: fn(&mut Context, Payload) -> Vec<Event<Channel, Payload>>) -> bool {
-    match existing {
-      Some(existing_vec) => {
-        existing_vec.push(listener);
-        true
-      }
-      None => false
+  pubsub.subscribe("created".to_string(), created);
+  pubsub.subscribe("deleted".to_string(), deleted);
+
+  let log = vec![
+    Event { channel: "created".to_string(), payload: "a".to_string() },
+    Event { channel: "deleted".to_string(), payload: "a".to_string() }
+  ];
+
+  assert!(pubsub.publish_slice(&log) == 2);
+  assert!(log.len() == 2);
+  assert!(pubsub.context.seen == vec!["created a".to_string(), "deleted a".to_string()])
+}
+
+#[test]
+fn timing_should_record_listener_duration_per_channel() {
+  use std::thread;
+
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_timing(&mut test_context);
+
+  fn slow_listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    thread::sleep(Duration::from_millis(5));
+    Vec::new()
+  }
+
+  pubsub.subscribe("slow".to_string(), slow_listener);
+  pubsub.publish(Event { channel: "slow".to_string(), payload: "go".to_string() });
+
+  assert!(pubsub.timing().total["slow"] >= Duration::from_millis(5));
+  assert!(pubsub.timing().max["slow"] > Duration::ZERO)
+}
+
+#[test]
+fn timing_should_stay_empty_when_disabled() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    Vec::new()
+  }
+
+  pubsub.subscribe("fast".to_string(), listener);
+  pubsub.publish(Event { channel: "fast".to_string(), payload: "go".to_string() });
+
+  assert!(pubsub.timing().total.is_empty())
+}
+
+#[test]
+fn peek_next_mut_should_rewrite_payload_before_step() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "draft".to_string() });
+
+  if let Some(payload) = pubsub.peek_next_mut() {
+    payload.push_str(" (edited)");
+  }
+  assert!(!pubsub.step());
+
+  assert!(pubsub.context.seen == vec!["draft (edited)".to_string()])
+}
+
+#[test]
+fn peek_next_mut_should_rewrite_event_dispatcher_picks_next() {
+  #[derive(Clone)]
+  struct Lifo;
+
+  impl<Channel, Payload> Dispatcher<Channel, Payload> for Lifo {
+    fn next(&mut self, queue: &EventQueue<Channel, Payload>) -> Option<usize> {
+      queue.len().checked_sub(1)
     }
   }
+
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_dispatcher(&mut test_context, Lifo);
+
+  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(msg);
+    Vec::new()
+  }
+
+  pubsub.subscribe("test channel".to_string(), listener);
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "first".to_string() });
+  pubsub.enqueue(Event { channel: "test channel".to_string(), payload: "second".to_string() });
+
+  if let Some(payload) = pubsub.peek_next_mut() {
+    payload.push_str(" (edited)");
+  }
+  assert!(pubsub.step());
+  assert!(!pubsub.step());
+
+  assert!(pubsub.context.seen == vec!["second (edited)".to_string(), "first".to_string()])
 }
 
 #[test]
-fn no_listeners_should_not_change() {
+fn fanout_should_copy_payload_to_each_target() {
   struct TestContext {
-    data: isize
+    seen: Vec<String>
   }
 
-  let mut test_context = TestContext { data: 0 };
+  let mut test_context = TestContext { seen: Vec::new() };
   let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
-  let event = Event {
-    payload: "test payload".to_string(),
-    channel: "test channel".to_string()
-  };
-  pubsub.publish(event);
 
-  assert!(pubsub.context.data == 0)
+  fn a_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("a {}", msg));
+    Vec::new()
+  }
+
+  fn b_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    context.seen.push(format!("b {}", msg));
+    Vec::new()
+  }
+
+  pubsub.subscribe_fanout("src".to_string(), vec!["a".to_string(), "b".to_string()]);
+  pubsub.subscribe("a".to_string(), a_listener);
+  pubsub.subscribe("b".to_string(), b_listener);
+
+  assert!(pubsub.publish(Event { channel: "src".to_string(), payload: "x".to_string() }) == 3);
+  assert!(pubsub.context.seen == vec!["a x".to_string(), "b x".to_string()])
 }
 
 #[test]
-fn noop_listener_should_not_change() {
+fn request_should_return_first_reply_payload() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn handler(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![
+      Event { channel: "reply".to_string(), payload: format!("pong {}", msg) },
+      Event { channel: "reply".to_string(), payload: "late".to_string() }
+    ]
+  }
+
+  pubsub.subscribe("req".to_string(), handler);
+
+  let reply = pubsub.request(Event { channel: "req".to_string(), payload: "ping".to_string() }, "reply".to_string());
+  assert!(reply == Some("pong ping".to_string()));
+  assert!(pubsub.request(Event { channel: "other".to_string(), payload: "ping".to_string() }, "reply".to_string()).is_none())
+}
+
+#[test]
+fn request_should_ignore_replies_from_other_cascades() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn noise(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "reply".to_string(), payload: "noise".to_string() }]
+  }
+
+  fn handler(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "work".to_string(), payload: msg }]
+  }
+
+  fn worker(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "reply".to_string(), payload: format!("done {}", msg) }]
+  }
+
+  pubsub.subscribe("noise".to_string(), noise);
+  pubsub.subscribe("req".to_string(), handler);
+  pubsub.subscribe("work".to_string(), worker);
+  pubsub.enqueue(Event { channel: "noise".to_string(), payload: "tick".to_string() });
+  pubsub.enqueue(Event { channel: "reply".to_string(), payload: "stale".to_string() });
+
+  let reply = pubsub.request(Event { channel: "req".to_string(), payload: "job".to_string() }, "reply".to_string());
+  assert!(reply == Some("done job".to_string()));
+  assert!(pubsub.queue_len() == 0)
+}
+
+#[test]
+fn request_should_keep_its_reply_when_queue_aware_listener_removes_pending_events() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+
+  fn noise(_context: &mut TestContext, _msg: String, queue: &mut EventQueue<String, String>) {
+    queue.retain(|pending| pending.channel != "dup");
+  }
+
+  fn handler(_context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "reply".to_string(), payload: "pong".to_string() }]
+  }
+
+  pubsub.subscribe_queue_aware("noise".to_string(), noise);
+  pubsub.subscribe("req".to_string(), handler);
+  pubsub.enqueue(Event { channel: "noise".to_string(), payload: "tick".to_string() });
+  pubsub.enqueue(Event { channel: "dup".to_string(), payload: "tick".to_string() });
+
+  let reply = pubsub.request(Event { channel: "req".to_string(), payload: "ping".to_string() }, "reply".to_string());
+  assert!(reply == Some("pong".to_string()))
+}
+
+#[test]
+fn debounced_listener_should_drop_events_within_interval() {
   struct TestContext {
     data: isize
   }
 
   let mut test_context = TestContext { data: 0 };
   let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
-  let event = Event {
-    payload: "test payload".to_string(),
-    channel: "test channel".to_string()
-  };
 
-  fn noop_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.data += 1;
     Vec::new()
   }
 
-  pubsub.subscribe("test channel".to_string(), noop_listener);
-  pubsub.publish(event);
+  pubsub.subscribe_debounced("ticks".to_string(), Duration::from_secs(60), listener);
 
-  assert!(pubsub.context.data == 0)
+  let invoked: usize = (0..3).map(|_| pubsub.publish(Event { channel: "ticks".to_string(), payload: "tick".to_string() })).sum();
+  assert!(invoked == 1);
+  assert!(pubsub.context.data == 1)
 }
 
 #[test]
-fn listener_on_same_channel_should_change() {
+fn debounce_window_should_not_be_shared_with_copies() {
+  #[derive(Clone)]
   struct TestContext {
     data: isize
   }
 
   let mut test_context = TestContext { data: 0 };
+  let mut copy_context = TestContext { data: 0 };
   let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
-  let event = Event {
-    payload: "test payload".to_string(),
-    channel: "test channel".to_string()
-  };
+  let event = Event { channel: "ticks".to_string(), payload: "tick".to_string() };
 
-  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
     context.data += 1;
     Vec::new()
-  };
+  }
 
-  pubsub.subscribe("test channel".to_string(), listener);
-  pubsub.publish(event);
+  pubsub.subscribe_debounced("ticks".to_string(), Duration::from_secs(60), listener);
+
+  assert!(pubsub.simulate(event.clone()).data == 1);
+  let mut copy = pubsub.clone_config_with(&mut copy_context);
+  assert!(copy.publish(event.clone()) == 1);
 
+  assert!(pubsub.publish(event) == 1);
   assert!(pubsub.context.data == 1)
 }
 
 #[test]
-fn listener_on_different_channel_should_not_change() {
+fn channel_normalizer_should_route_case_insensitively() {
   struct TestContext {
     data: isize
   }
 
   let mut test_context = TestContext { data: 0 };
-  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
-  let event = Event {
-    payload: "test payload".to_string(),
-    channel: "test channel".to_string()
-  };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_channel_normalizer(&mut test_context, |channel| channel.to_lowercase());
 
-  fn listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
     context.data += 1;
     Vec::new()
-  };
+  }
 
-  pubsub.subscribe("diff channel".to_string(), listener);
-  pubsub.publish(event);
+  pubsub.subscribe("test".to_string(), listener);
+  pubsub.subscribe("Test".to_string(), listener);
 
-  assert!(pubsub.context.data == 0)
+  assert!(pubsub.listener_count(&"TEST".to_string()) == 2);
+  assert!(pubsub.publish(Event { channel: "TEST".to_string(), payload: "hello".to_string() }) == 2);
+  assert!(pubsub.context.data == 2)
 }
 
 #[test]
-fn listener_can_trigger_more_events() {
+fn channel_normalizer_should_apply_to_every_channel_argument() {
   struct TestContext {
     data: isize
   }
+
   let mut test_context = TestContext { data: 0 };
-  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
-  let event = Event {
-    payload: "test payload".to_string(),
-    channel: "test channel".to_string()
-  };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_channel_normalizer(&mut test_context, |channel| channel.to_lowercase());
+  pubsub.history_capacity = 10;
 
-  fn listener_with_triggers(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
     context.data += 1;
-    vec![Event {
-        channel: "test channel 2".to_string(),
-        payload: "payload".to_string()
-    }]
-  };
+    Vec::new()
+  }
+
+  fn replier(_context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+    vec![Event { channel: "Reply".to_string(), payload: msg }]
+  }
+
+  assert!(pubsub.subscribe_unique("TEST".to_string(), listener));
+  assert!(!pubsub.subscribe_unique("test".to_string(), listener));
+  assert!(pubsub.contains_listener(&"Test".to_string(), listener));
+
+  pubsub.set_listeners("TEST".to_string(), vec![listener, listener]);
+  assert!(pubsub.listener_count(&"test".to_string()) == 2);
+  assert!(pubsub.unsubscribe(&"TEST".to_string(), listener));
+  assert!(pubsub.listener_count(&"test".to_string()) == 1);
+
+  assert!(pubsub.rename_channel(&"TEST".to_string(), "Renamed".to_string()));
+  assert!(pubsub.listener_count(&"renamed".to_string()) == 1);
+
+  pubsub.register_channel("RENAMED".to_string());
+  assert!(pubsub.publish_checked(Event { channel: "Renamed".to_string(), payload: "hello".to_string() }) == Ok(1));
+  assert!(pubsub.history_for(&"RENAMED".to_string()).count() == 1);
+
+  pubsub.subscribe_with_role("RPC".to_string(), Role::Command, replier);
+  assert!(pubsub.publish_event(Event { channel: "rpc".to_string(), payload: "ping".to_string() }).is_err());
+  assert!(pubsub.request(Event { channel: "rpc".to_string(), payload: "ping".to_string() }, "REPLY".to_string()) == Some("ping".to_string()));
+
+  assert!(pubsub.unsubscribe_all(&"RENAMED".to_string()) == 1);
+  assert!(pubsub.channel_count() == 1);
+  assert!(pubsub.context.data == 1)
+}
+
+#[test]
+fn set_channel_normalizer_should_rekey_existing_channels() {
+  struct TestContext {
+    seen: Vec<String>
+  }
+
+  let mut test_context = TestContext { seen: Vec::new() };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::with_max_depth(&mut test_context, 2);
+
+  fn low_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("low".to_string());
+    Vec::new()
+  }
+
+  fn high_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("high".to_string());
+    Vec::new()
+  }
+
+  fn prefix_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("prefix".to_string());
+    Vec::new()
+  }
+
+  fn range_listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
+    context.seen.push("range".to_string());
+    Vec::new()
+  }
+
+  pubsub.subscribe_with_priority("test".to_string(), 0, low_listener);
+  pubsub.subscribe_with_priority("TEST".to_string(), 10, high_listener);
+  pubsub.register_channel("TEST".to_string());
+  pubsub.subscribe_prefix("User.".to_string(), prefix_listener);
+  pubsub.subscribe_range("M".to_string().."P".to_string(), range_listener);
+  pubsub.set_channel_normalizer(|channel| channel.to_lowercase());
+
+  assert!(pubsub.channel_count() == 1);
+  assert!(pubsub.publish_checked(Event { channel: "Test".to_string(), payload: "payload".to_string() }) == Ok(2));
+  assert!(pubsub.publish(Event { channel: "User.login".to_string(), payload: "payload".to_string() }) == 1);
+  assert!(pubsub.publish(Event { channel: "Node".to_string(), payload: "payload".to_string() }) == 1);
+  assert!(pubsub.context.seen == vec!["high".to_string(), "low".to_string(), "prefix".to_string(), "range".to_string()])
+}
+
+#[test]
+fn publish_should_record_listener_error() {
+  struct TestContext;
+
+  let mut test_context = TestContext;
+  let mut pubsub: Pubsub<TestContext, String, String, String> = Pubsub::new(&mut test_context);
+
+  fn failing_listener(_context: &mut TestContext, _msg: String) -> Result<Vec<Event<String, String>>, String> {
+    Err("listener failed".to_string())
+  }
 
-  fn plain_listener(context: &mut TestContext, msg: String) -> Vec<Event<String, String>> {
+  pubsub.subscribe_try("test channel".to_string(), failing_listener);
+  assert!(pubsub.listener_error().is_none());
+
+  assert!(pubsub.publish(Event { channel: "test channel".to_string(), payload: "payload".to_string() }) == 1);
+  assert!(pubsub.listener_error() == Some(&"listener failed".to_string()))
+}
+
+#[test]
+fn setters_should_combine_bus_options() {
+  struct TestContext {
+    data: isize
+  }
+
+  let mut test_context = TestContext { data: 0 };
+  let mut pubsub: Pubsub<TestContext, String, String> = Pubsub::new(&mut test_context);
+  pubsub.set_history_capacity(1);
+  pubsub.set_metrics(true);
+  pubsub.set_strict(true);
+  pubsub.set_coalescing(true);
+
+  fn listener(context: &mut TestContext, _msg: String) -> Vec<Event<String, String>> {
     context.data += 1;
     Vec::new()
   }
 
-  pubsub.subscribe("test channel".to_string(), listener_with_triggers);
-  pubsub.subscribe("test channel 2".to_string(), plain_listener);
-  pubsub.publish(event);
+  pubsub.subscribe("a".to_string(), listener);
+  pubsub.enqueue(Event { channel: "a".to_string(), payload: "same".to_string() });
+  pubsub.enqueue(Event { channel: "a".to_string(), payload: "same".to_string() });
 
-  assert!(pubsub.context.data == 2)
+  assert!(pubsub.publish(Event { channel: "missing".to_string(), payload: "lost".to_string() }) == 1);
+  assert!(pubsub.last_error() == Some(&BusError::NoListeners("missing".to_string())));
+  assert!(pubsub.metrics().total_events_processed == 2);
+  assert!(pubsub.history().len() == 1);
+  assert!(pubsub.context.data == 1)
 }